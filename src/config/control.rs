@@ -0,0 +1,16 @@
+use serde::Deserialize;
+
+// 控制接口配置，用于在运行时查询和修改代理状态
+#[derive(Debug, Deserialize, Clone)]
+pub struct Control {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Control {
+    pub async fn init(&self) {
+        let control = self.clone();
+        // 监听服务为 loop 需要放在单独的工作携程中
+        tokio::spawn(async move { control.listen().await });
+    }
+}
@@ -1,14 +1,17 @@
+pub mod control;
 pub mod inbound;
 pub mod info;
 pub mod mode;
 pub mod outbound;
 pub mod router;
+pub mod rule_set;
 
 use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::collections::HashSet;
 use tracing::warn;
 
+use control::Control;
 use inbound::Inbound;
 use info::Info;
 use mode::Mode;
@@ -22,6 +25,7 @@ pub struct Config {
     pub router: Router,
     pub inbound: Vec<Inbound>,
     pub outbound: Vec<Outbound>,
+    pub control: Option<Control>,
 }
 
 impl Config {
@@ -29,7 +33,18 @@ impl Config {
         let file_path = "config.yaml";
         let config_str = std::fs::read_to_string(file_path)
             .with_context(|| format!("读取配置文件失败: {}", file_path))?;
-        let config: Config = serde_yaml::from_str(&config_str).with_context(|| "解析文件失败")?;
+        let mut config: Config =
+            serde_yaml::from_str(&config_str).with_context(|| "解析文件失败")?;
+
+        // 启动日志，配置验证和加载中的警告需要在此之后才能输出
+        config.info.init();
+
+        // 补充内置出站，同名的自定义出站优先
+        for builtin in Outbound::builtin() {
+            if !config.outbound.iter().any(|o| o.name() == builtin.name()) {
+                config.outbound.push(builtin);
+            }
+        }
 
         config.validate()?;
         Ok(config)
@@ -43,6 +58,12 @@ impl Config {
             if !inbound_name_hash_list.insert(name.to_string()) {
                 return Err(anyhow::anyhow!("未知的入站名: {}", name));
             }
+            if let Inbound::Socks5(socks5) = inbound
+                && socks5.auth_enable
+                && socks5.user.is_empty()
+            {
+                return Err(anyhow::anyhow!("入站 '{}' 启用了认证但没有配置用户", name));
+            }
         }
 
         // 验证出站配置
@@ -52,6 +73,15 @@ impl Config {
             if !outbound_name_hash_list.insert(name.to_string()) {
                 return Err(anyhow::anyhow!("未知的出站名: {}", name));
             }
+            if let Outbound::Socks5(socks5) = outbound
+                && socks5.auth_enable
+                && (socks5.username.is_none() || socks5.password.is_none())
+            {
+                return Err(anyhow::anyhow!(
+                    "出站 '{}' 启用了认证但没有配置用户名或密码",
+                    name
+                ));
+            }
         }
 
         // 验证路由引用
@@ -74,16 +104,55 @@ impl Config {
             if !outbound_name_hash_list.contains(&rule.outbound) {
                 return Err(anyhow::anyhow!("规则出站 '{}' 没有找到", rule.outbound));
             }
+            rule.validate()?;
+            for name in rule
+                .flatten()
+                .into_iter()
+                .filter(|rule| matches!(rule.r#type, router::RuleType::Inbound))
+                .flat_map(|rule| &rule.source)
+            {
+                if !inbound_name_hash_list.contains(name) {
+                    return Err(anyhow::anyhow!("规则入站 '{}' 没有找到", name));
+                }
+            }
         }
 
+        // 验证 GeoIP 数据库，文件缺失或损坏时拒绝启动。规则集中的 geoip 规则在加载规则集时检查
+        self.router.check_geoip(&self.router.rule)?;
+        self.router.load_geoip()?;
+
+        // 验证 geosite 数据及其中的分类，规则集中的 geosite 规则在加载规则集时检查
+        let geosite = self.router.load_geosite(self.router.rule.iter())?;
+        self.router.check_geosite(&self.router.rule, &geosite)?;
+
         // 验证路由规则集
         for rule_set in &self.router.rule_set {
-            let outbound_name = match rule_set {
-                router::RuleSet::RuleSetLocal(local) => &local.outbound,
-                router::RuleSet::RuleSetRemote(remote) => &remote.outbound,
-            };
-            if !outbound_name_hash_list.contains(outbound_name) {
-                warn!("规则集的出战 '{}' 没有找到", outbound_name);
+            if !outbound_name_hash_list.contains(rule_set.outbound()) {
+                warn!("规则集的出战 '{}' 没有找到", rule_set.outbound());
+            }
+            match rule_set {
+                router::RuleSet::RuleSetLocal(local) => {
+                    if !std::path::Path::new(&local.path).exists() {
+                        warn!("规则集 '{}' 的文件 '{}' 不存在", local.name, local.path);
+                    }
+                }
+                router::RuleSet::RuleSetRemote(remote) => {
+                    if let Some(name) = &remote.download_outbound
+                        && !outbound_name_hash_list.contains(name)
+                    {
+                        return Err(anyhow::anyhow!(
+                            "规则集 '{}' 的下载出站 '{}' 没有找到",
+                            remote.name,
+                            name
+                        ));
+                    }
+                    if remote.update_interval == 0 {
+                        return Err(anyhow::anyhow!(
+                            "规则集 '{}' 的更新间隔必须大于 0",
+                            remote.name
+                        ));
+                    }
+                }
             }
         }
 
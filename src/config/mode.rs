@@ -1,9 +1,28 @@
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 pub enum Mode {
     Rule,
     Direct,
     Global,
 }
+
+impl Mode {
+    pub fn parse(s: &str) -> Option<Mode> {
+        match s.trim().to_lowercase().as_str() {
+            "rule" => Some(Mode::Rule),
+            "direct" => Some(Mode::Direct),
+            "global" => Some(Mode::Global),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Rule => "rule",
+            Mode::Direct => "direct",
+            Mode::Global => "global",
+        }
+    }
+}
@@ -1,10 +1,17 @@
+use anyhow::Result;
 use serde::Deserialize;
 
-use crate::protocol::{http::Http, socks5::Socks5};
+use crate::protocol::{
+    address::Address, direct::Direct, http::Http, reject::Reject, socks5::Socks5,
+    stream::ProxyStream, udp::Datagram,
+};
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "lowercase", tag = "type")]
 pub enum Outbound {
+    Direct(Direct),
+    Reject(Reject),
+    Blackhole(Reject),
     Http(Http),
     Socks5(Socks5),
 }
@@ -12,8 +19,51 @@ pub enum Outbound {
 impl Outbound {
     pub fn name(&self) -> &str {
         match self {
+            Outbound::Direct(direct) => &direct.name,
+            Outbound::Reject(reject) => &reject.name,
+            Outbound::Blackhole(blackhole) => &blackhole.name,
             Outbound::Http(http) => &http.name,
             Outbound::Socks5(socks5) => &socks5.name,
         }
     }
+
+    // 内置的直连出站
+    pub fn direct() -> Outbound {
+        Outbound::Direct(Direct {
+            name: "direct".to_string(),
+        })
+    }
+
+    // 内置出站，配置中没有同名出站时可以直接引用
+    pub fn builtin() -> Vec<Outbound> {
+        vec![
+            Outbound::direct(),
+            Outbound::Reject(Reject {
+                name: "reject".to_string(),
+            }),
+            Outbound::Blackhole(Reject {
+                name: "blackhole".to_string(),
+            }),
+        ]
+    }
+
+    pub async fn connect(&self, target: &Address) -> Result<ProxyStream> {
+        match self {
+            Outbound::Direct(direct) => Ok(Box::new(direct.connect(target).await?)),
+            Outbound::Reject(reject) => reject.reject(false),
+            Outbound::Blackhole(blackhole) => blackhole.reject(true),
+            Outbound::Http(http) => Ok(Box::new(http.connect(target).await?)),
+            Outbound::Socks5(socks5) => Ok(Box::new(socks5.connect(target).await?)),
+        }
+    }
+
+    pub async fn bind_udp(&self) -> Result<Datagram> {
+        match self {
+            Outbound::Direct(direct) => Ok(Datagram::Direct(direct.bind_udp().await?)),
+            Outbound::Reject(reject) => reject.reject(false),
+            Outbound::Blackhole(blackhole) => blackhole.reject(true),
+            Outbound::Http(http) => Err(anyhow::anyhow!("HTTP 出站 '{}' 不支持 UDP", http.name)),
+            Outbound::Socks5(socks5) => Ok(Datagram::Socks5(socks5.udp_associate().await?)),
+        }
+    }
 }
@@ -1,43 +1,303 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result, ensure};
 use serde::Deserialize;
+use tracing::{error, info};
 
-#[derive(Debug, Deserialize)]
+use super::rule_set::RuleSetFormat;
+use crate::service::{
+    geosite::{GeoSite, load_geosite, select_geosite},
+    matcher::{GeoIpReader, open_geoip, parse_cidr, parse_port_range, parse_regex, parse_uid},
+};
+
+#[derive(Debug, Deserialize, Clone)]
 pub struct Router {
     pub global: String,
     pub default: String,
     pub rule: Vec<Rule>,
     pub rule_set: Vec<RuleSet>,
+    // GeoIP 数据库路径 (MaxMind mmdb 格式)，geoip 规则需要
+    pub geoip: Option<String>,
+    // geosite 数据路径 (v2ray geosite.dat 或文本列表目录)，geosite 规则需要
+    pub geosite: Option<String>,
 }
 
 impl Router {
-    pub fn init(&self) -> Vec<Rule> {
-        let mut rule_list = Vec::new();
+    pub fn load_geoip(&self) -> Result<Option<Arc<GeoIpReader>>> {
+        match &self.geoip {
+            Some(path) => Ok(Some(Arc::new(open_geoip(path)?))),
+            None => Ok(None),
+        }
+    }
+
+    // 国家代码的 geoip 规则需要配置 GeoIP 数据库，"private" 不需要
+    pub fn check_geoip(&self, rule_list: &[Rule]) -> Result<()> {
+        let need_geoip = rule_list
+            .iter()
+            .flat_map(Rule::flatten)
+            .filter(|rule| matches!(rule.r#type, RuleType::GeoIp))
+            .flat_map(|rule| &rule.source)
+            .any(|code| !code.eq_ignore_ascii_case("private"));
+        ensure!(
+            !need_geoip || self.geoip.is_some(),
+            "使用 geoip 规则需要配置 router.geoip"
+        );
+        Ok(())
+    }
+
+    // geosite 规则需要配置 geosite 数据，用到的分类都必须存在，带有属性时至少要有一条满足属性的域名
+    pub fn check_geosite(&self, rule_list: &[Rule], geosite: &GeoSite) -> Result<()> {
+        for code in geosite_code(rule_list.iter()) {
+            ensure!(
+                self.geosite.is_some(),
+                "使用 geosite 规则需要配置 router.geosite"
+            );
+            let domain_vec = select_geosite(geosite, code)
+                .with_context(|| format!("geosite 分类 '{}' 没有找到", code))?;
+            ensure!(
+                !domain_vec.is_empty(),
+                "geosite 分类 '{}' 中没有满足属性的域名",
+                code
+            );
+        }
+        Ok(())
+    }
+
+    // 加载规则中用到的 geosite 分类
+    pub fn load_geosite<'a>(&self, rule_iter: impl Iterator<Item = &'a Rule>) -> Result<GeoSite> {
+        let code_vec: Vec<String> = geosite_code(rule_iter).cloned().collect();
+        match &self.geosite {
+            Some(path) if !code_vec.is_empty() => load_geosite(path, &code_vec),
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    // 加载规则集，并一次加载所有规则用到的 geosite 分类。
+    // 加载失败或引用了不可用的 geoip、geosite 数据的规则集记录错误后跳过，不影响其余规则
+    pub fn init(&self) -> Result<(Vec<Rule>, GeoSite)> {
+        let mut rule_set_vec = Vec::new();
         for rule_set in &self.rule_set {
-            rule_list.extend(rule_set.init());
+            let result = rule_set.init().and_then(|rule_vec| {
+                self.check_geoip(&rule_vec)
+                    .with_context(|| format!("规则集 '{}' 无法使用", rule_set.name()))?;
+                Ok(rule_vec)
+            });
+            match result {
+                Ok(rule_vec) => rule_set_vec.push((rule_set.name(), rule_vec)),
+                Err(e) => error!("{:#}", e),
+            }
+        }
+
+        let geosite = self.load_geosite(
+            rule_set_vec
+                .iter()
+                .flat_map(|(_, rule_vec)| rule_vec)
+                .chain(&self.rule),
+        )?;
+        let mut rule_list = Vec::new();
+        for (name, rule_vec) in rule_set_vec {
+            match self
+                .check_geosite(&rule_vec, &geosite)
+                .with_context(|| format!("规则集 '{}' 无法使用", name))
+            {
+                Ok(()) => rule_list.extend(rule_vec),
+                Err(e) => error!("{:#}", e),
+            }
         }
         rule_list.extend(self.rule.clone());
-        rule_list
+        Ok((rule_list, geosite))
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(rename_all = "lowercase")]
+// 规则 (包括子规则) 中用到的 geosite 分类
+fn geosite_code<'a>(rule_iter: impl Iterator<Item = &'a Rule>) -> impl Iterator<Item = &'a String> {
+    rule_iter
+        .flat_map(Rule::flatten)
+        .filter(|rule| matches!(rule.r#type, RuleType::GeoSite))
+        .flat_map(|rule| &rule.source)
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
 pub enum RuleType {
+    // 域名完全匹配，与 domain_full 相同
     Domain,
+    // 域名完全匹配
+    DomainFull,
+    // 域名后缀匹配，按标签边界匹配域名本身及其子域名
+    DomainSuffix,
+    // 域名包含关键字
     Keyword,
+    // 域名匹配正则
+    DomainRegex,
+    // 目标 IP 属于网段，IPv4 和 IPv6 均可，IPv4 映射的 IPv6 网段 (::ffff:a.b.c.d/n) 按 IPv4 匹配
+    IpCidr,
+    // 与 ip_cidr 相同，便于区分 IPv6 网段。不检查地址族，写入 IPv4 网段也按 IPv4 匹配
+    IpCidr6,
+    // 目标 IP 所属国家代码，"private" 表示私有地址
+    #[serde(rename = "geoip")]
+    GeoIp,
+    // 目标域名属于 geosite 分类
+    #[serde(rename = "geosite")]
+    GeoSite,
+    // 目标端口，支持单个端口和范围 ("443"、"8000-9000")
+    DstPort,
+    // 客户端 IP 属于网段
+    SrcIpCidr,
+    // 客户端端口，支持单个端口和范围
+    SrcPort,
+    // 连接来自的入站名
+    Inbound,
+    // 本机客户端的进程名
+    ProcessName,
+    // 本机客户端的可执行文件路径
+    ProcessPath,
+    // 本机客户端进程的用户 ID
+    Uid,
+    // 所有子规则都命中
+    And,
+    // 任一子规则命中
+    Or,
+    // 唯一的子规则没有命中
+    Not,
+}
+
+impl RuleType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RuleType::Domain => "domain",
+            RuleType::DomainFull => "domain_full",
+            RuleType::DomainSuffix => "domain_suffix",
+            RuleType::Keyword => "keyword",
+            RuleType::DomainRegex => "domain_regex",
+            RuleType::IpCidr => "ip_cidr",
+            RuleType::IpCidr6 => "ip_cidr6",
+            RuleType::GeoIp => "geoip",
+            RuleType::GeoSite => "geosite",
+            RuleType::DstPort => "dst_port",
+            RuleType::SrcIpCidr => "src_ip_cidr",
+            RuleType::SrcPort => "src_port",
+            RuleType::Inbound => "inbound",
+            RuleType::ProcessName => "process_name",
+            RuleType::ProcessPath => "process_path",
+            RuleType::Uid => "uid",
+            RuleType::And => "and",
+            RuleType::Or => "or",
+            RuleType::Not => "not",
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Rule {
     pub r#type: RuleType,
+    #[serde(default)]
     pub source: Vec<String>,
+    // 子规则不需要出站
+    #[serde(default)]
     pub outbound: String,
+    // IP 规则是否先解析域名目标，再用解析结果匹配
+    #[serde(default)]
+    pub resolve: bool,
+    // and/or/not 规则的子规则
+    #[serde(default)]
+    pub rule: Vec<Rule>,
+    // 规则来自的规则集名，直接写在配置中的规则为 None
+    #[serde(skip)]
+    pub rule_set: Option<String>,
 }
 
+// 输出为 "类型 [条目, ...]"，and/or/not 规则输出为 "类型 (子规则, ...)"
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.r#type.as_str())?;
+        if !self.source.is_empty() {
+            write!(f, " [{}]", self.source.join(", "))?;
+        }
+        if !self.rule.is_empty() {
+            let rule_vec: Vec<String> = self.rule.iter().map(Rule::to_string).collect();
+            write!(f, " ({})", rule_vec.join(", "))?;
+        }
+        if let Some(rule_set) = &self.rule_set {
+            write!(f, " 来自规则集 '{}'", rule_set)?;
+        }
+        Ok(())
+    }
+}
+
+impl Rule {
+    // 规则本身及其包含的所有子规则
+    pub fn flatten(&self) -> Vec<&Rule> {
+        let mut rule_vec = vec![self];
+        for rule in &self.rule {
+            rule_vec.extend(rule.flatten());
+        }
+        rule_vec
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self.r#type {
+            RuleType::And | RuleType::Or => {
+                ensure!(
+                    !self.rule.is_empty(),
+                    "{:?} 规则至少需要一条子规则",
+                    self.r#type
+                );
+            }
+            RuleType::Not => {
+                ensure!(self.rule.len() == 1, "Not 规则需要且只能有一条子规则");
+            }
+            _ => {
+                ensure!(self.rule.is_empty(), "{:?} 规则不能包含子规则", self.r#type);
+                // source 缺省为空列表，空规则永远不会命中
+                ensure!(
+                    !self.source.is_empty(),
+                    "{:?} 规则的 source 不能为空",
+                    self.r#type
+                );
+            }
+        }
+        for rule in &self.rule {
+            rule.validate()?;
+        }
+        match self.r#type {
+            RuleType::IpCidr | RuleType::IpCidr6 | RuleType::SrcIpCidr => {
+                for cidr in &self.source {
+                    parse_cidr(cidr)?;
+                }
+            }
+            RuleType::DstPort | RuleType::SrcPort => {
+                for range in &self.source {
+                    parse_port_range(range)?;
+                }
+            }
+            RuleType::DomainRegex => {
+                for pattern in &self.source {
+                    parse_regex(pattern)?;
+                }
+            }
+            RuleType::Uid => {
+                for uid in &self.source {
+                    parse_uid(uid)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+// 没有 url 字段的规则集才是本地规则集，因此远程规则集需要先尝试
 #[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
 pub enum RuleSet {
-    RuleSetLocal(RuleSetLocal),
     RuleSetRemote(RuleSetRemote),
+    RuleSetLocal(RuleSetLocal),
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -45,51 +305,110 @@ pub struct RuleSetLocal {
     pub name: String,
     pub path: String,
     pub outbound: String,
+    #[serde(default)]
+    pub format: RuleSetFormat,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(rename_all = "lowercase")]
 pub struct RuleSetRemote {
     pub name: String,
+    // 下载结果的缓存路径
     pub path: String,
     pub url: String,
     pub outbound: String,
+    #[serde(default)]
+    pub format: RuleSetFormat,
+    // 下载规则集时使用的出站，默认直连
+    pub download_outbound: Option<String>,
+    // 更新间隔 (秒)
+    #[serde(default = "default_update_interval")]
+    pub update_interval: u64,
+}
+
+fn default_update_interval() -> u64 {
+    24 * 60 * 60
 }
 
 #[derive(Debug, Deserialize)]
 pub struct RuleSetRule {
     pub r#type: RuleType,
+    #[serde(default)]
     pub source: Vec<String>,
+    #[serde(default)]
+    pub resolve: bool,
+    #[serde(default)]
+    pub rule: Vec<Rule>,
 }
 
 impl RuleSetRule {
-    pub fn to_rule(self, outbound: &str) -> Rule {
+    pub fn to_rule(self, rule_set: &str, outbound: &str) -> Rule {
         Rule {
             r#type: self.r#type,
             source: self.source,
             outbound: outbound.to_string(),
+            resolve: self.resolve,
+            rule: self.rule,
+            rule_set: Some(rule_set.to_string()),
         }
     }
 }
 
 impl RuleSet {
-    pub fn init(&self) -> Vec<Rule> {
+    pub fn name(&self) -> &str {
         match self {
-            RuleSet::RuleSetLocal(local) => {
-                let mut rules = Vec::new();
-                if let Ok(content) = std::fs::read_to_string(&local.path) {
-                    let rule_set_rule_vec: Vec<RuleSetRule> =
-                        serde_yaml::from_str(&content).unwrap();
-                    for rule in rule_set_rule_vec {
-                        rules.push(rule.to_rule(&local.outbound));
-                    }
-                }
-                rules
-            }
-            RuleSet::RuleSetRemote(_remote) => {
-                // TODO
-                vec![]
-            }
+            RuleSet::RuleSetLocal(local) => &local.name,
+            RuleSet::RuleSetRemote(remote) => &remote.name,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            RuleSet::RuleSetLocal(local) => &local.path,
+            RuleSet::RuleSetRemote(remote) => &remote.path,
+        }
+    }
+
+    pub fn format(&self) -> RuleSetFormat {
+        match self {
+            RuleSet::RuleSetLocal(local) => local.format,
+            RuleSet::RuleSetRemote(remote) => remote.format,
+        }
+    }
+
+    pub fn outbound(&self) -> &str {
+        match self {
+            RuleSet::RuleSetLocal(local) => &local.outbound,
+            RuleSet::RuleSetRemote(remote) => &remote.outbound,
+        }
+    }
+
+    // 加载规则集中的规则，远程规则集加载的是下载缓存。
+    // 文件不存在时跳过，无法读取或解析失败时返回错误
+    pub fn init(&self) -> Result<Vec<Rule>> {
+        // 本地规则集文件不存在时配置验证已经给出警告，远程规则集会在下载后加载
+        if !Path::new(self.path()).exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read(self.path())
+            .with_context(|| format!("读取规则集 '{}' 失败: {}", self.name(), self.path()))?;
+        let rule_vec = self.parse(&content)?;
+        info!("规则集 '{}' 加载了 {} 条规则", self.name(), rule_vec.len());
+        Ok(rule_vec)
+    }
+
+    // 解析规则集内容，错误信息带有规则集名、文件名和行号
+    pub fn parse(&self, content: &[u8]) -> Result<Vec<Rule>> {
+        let (name, path) = (self.name(), self.path());
+        let rule_vec = self
+            .format()
+            .decode(content, name, self.outbound())
+            .with_context(|| format!("解析规则集 '{}' 失败: {}", name, path))?;
+        for (index, rule) in rule_vec.iter().enumerate() {
+            rule.validate().with_context(|| {
+                format!("规则集 '{}' 的第 {} 条规则无效: {}", name, index + 1, path)
+            })?;
         }
+        Ok(rule_vec)
     }
 }
@@ -0,0 +1,424 @@
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use serde::Deserialize;
+use tracing::warn;
+
+use super::router::{Rule, RuleSetRule, RuleType};
+
+// 规则集文件格式
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleSetFormat {
+    // 本项目的 YAML 规则列表: [{type, source, resolve, rule}]
+    #[default]
+    Yaml,
+    // Clash classical 格式: payload: ["DOMAIN-SUFFIX,foo.com", "IP-CIDR,10.0.0.0/8,no-resolve"]
+    Classical,
+    // 每行一个域名，与 Clash 的 domain 列表相同: "foo.com" 完全匹配，"+.foo.com" 匹配域名本身及子域名，
+    // ".foo.com" 只匹配子域名，"*" 匹配一级标签。"#" 开头的行为注释
+    Text,
+    // 由 compile 子命令预编译的二进制格式
+    Binary,
+}
+
+impl RuleSetFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        match format.trim().to_ascii_lowercase().as_str() {
+            "yaml" => Ok(RuleSetFormat::Yaml),
+            "classical" => Ok(RuleSetFormat::Classical),
+            "text" => Ok(RuleSetFormat::Text),
+            "binary" => Ok(RuleSetFormat::Binary),
+            _ => bail!("未知的规则集格式: {}", format),
+        }
+    }
+
+    // 解析规则集内容，规则使用规则集的名字和出站
+    pub fn decode(&self, content: &[u8], name: &str, outbound: &str) -> Result<Vec<Rule>> {
+        let rule_set_rule_vec = match self {
+            // serde_yaml 的错误信息中带有行号和列号
+            RuleSetFormat::Yaml => serde_yaml::from_slice(content)?,
+            RuleSetFormat::Classical => decode_classical(content)?,
+            RuleSetFormat::Text => decode_text(content)?,
+            RuleSetFormat::Binary => decode_binary(content)?,
+        };
+        Ok(rule_set_rule_vec
+            .into_iter()
+            .map(|rule| rule.to_rule(name, outbound))
+            .collect())
+    }
+}
+
+// ============================================================================
+// Clash classical 格式
+// 每条为 "类型,值[,no-resolve]"，IP 类规则默认解析域名目标，带 no-resolve 时不解析
+// ============================================================================
+
+#[derive(Debug, Deserialize)]
+struct ClassicalPayload {
+    payload: Vec<String>,
+}
+
+fn decode_classical(content: &[u8]) -> Result<Vec<RuleSetRule>> {
+    let payload: ClassicalPayload = serde_yaml::from_slice(content)?;
+    let mut rule_vec: Vec<RuleSetRule> = Vec::new();
+    for (index, line) in payload.payload.iter().enumerate() {
+        let mut part = line.split(',').map(str::trim);
+        let (Some(r#type), Some(value)) = (part.next(), part.next()) else {
+            bail!("第 {} 条规则格式无效: {}", index + 1, line);
+        };
+        let no_resolve = part.any(|option| option.eq_ignore_ascii_case("no-resolve"));
+        let r#type = match r#type.to_ascii_uppercase().as_str() {
+            "DOMAIN" => RuleType::DomainFull,
+            "DOMAIN-SUFFIX" => RuleType::DomainSuffix,
+            "DOMAIN-KEYWORD" => RuleType::Keyword,
+            "DOMAIN-REGEX" => RuleType::DomainRegex,
+            "IP-CIDR" => RuleType::IpCidr,
+            "IP-CIDR6" => RuleType::IpCidr6,
+            "GEOIP" => RuleType::GeoIp,
+            "GEOSITE" => RuleType::GeoSite,
+            "DST-PORT" => RuleType::DstPort,
+            "SRC-IP-CIDR" => RuleType::SrcIpCidr,
+            "SRC-PORT" => RuleType::SrcPort,
+            "IN-NAME" => RuleType::Inbound,
+            "PROCESS-NAME" => RuleType::ProcessName,
+            "PROCESS-PATH" => RuleType::ProcessPath,
+            "UID" => RuleType::Uid,
+            _ => {
+                warn!("忽略第 {} 条不支持的规则: {}", index + 1, line);
+                continue;
+            }
+        };
+        let resolve = !no_resolve
+            && matches!(
+                r#type,
+                RuleType::IpCidr | RuleType::IpCidr6 | RuleType::GeoIp
+            );
+
+        // 合并前逐条验证，错误信息中的序号对应 payload 中的条目
+        let entry = Rule {
+            r#type,
+            source: vec![value.to_string()],
+            outbound: String::new(),
+            resolve,
+            rule: Vec::new(),
+            rule_set: None,
+        };
+        entry
+            .validate()
+            .with_context(|| format!("第 {} 条规则无效: {}", index + 1, line))?;
+
+        // 相邻的同类规则合并为一条
+        match rule_vec.last_mut() {
+            Some(last) if last.r#type == r#type && last.resolve == resolve => {
+                last.source.extend(entry.source)
+            }
+            _ => rule_vec.push(RuleSetRule {
+                r#type,
+                source: entry.source,
+                resolve,
+                rule: Vec::new(),
+            }),
+        }
+    }
+    Ok(rule_vec)
+}
+
+// ============================================================================
+// 纯文本域名列表
+// ============================================================================
+
+fn decode_text(content: &[u8]) -> Result<Vec<RuleSetRule>> {
+    let content = std::str::from_utf8(content).context("域名列表不是有效的 UTF-8 文本")?;
+    let mut full_vec = Vec::new();
+    let mut suffix_vec = Vec::new();
+    let mut regex_vec = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.to_ascii_lowercase();
+        if let Some(domain) = line.strip_prefix("+.") {
+            suffix_vec.push(domain.to_string());
+        } else if let Some(domain) = line.strip_prefix('.') {
+            // 只匹配子域名，不匹配域名本身
+            regex_vec.push(format!("^.+\\.{}$", regex::escape(domain)));
+        } else if line.contains('*') {
+            // "*" 匹配一级标签
+            let pattern: Vec<String> = line.split('*').map(regex::escape).collect();
+            regex_vec.push(format!("^{}$", pattern.join("[^.]+")));
+        } else {
+            full_vec.push(line);
+        }
+    }
+    Ok([
+        (RuleType::DomainFull, full_vec),
+        (RuleType::DomainSuffix, suffix_vec),
+        (RuleType::DomainRegex, regex_vec),
+    ]
+    .into_iter()
+    .filter(|(_, source)| !source.is_empty())
+    .map(|(r#type, source)| RuleSetRule {
+        r#type,
+        source,
+        resolve: false,
+        rule: Vec::new(),
+    })
+    .collect())
+}
+
+// ============================================================================
+// 二进制格式
+// 文件头: "XPRS" 版本(u8) 规则数(varint)
+// 规则:   类型(u8) 是否解析(u8) 条目数(varint) [长度(varint) 内容]... 子规则数(varint) [子规则]...
+// 类型编号为 RULE_TYPE_LIST 中的下标，新类型只能追加在末尾
+// ============================================================================
+
+const BINARY_MAGIC: &[u8; 4] = b"XPRS";
+const BINARY_VERSION: u8 = 1;
+
+const RULE_TYPE_LIST: [RuleType; 19] = [
+    RuleType::Domain,
+    RuleType::DomainFull,
+    RuleType::DomainSuffix,
+    RuleType::Keyword,
+    RuleType::IpCidr,
+    RuleType::IpCidr6,
+    RuleType::GeoIp,
+    RuleType::GeoSite,
+    RuleType::DstPort,
+    RuleType::SrcIpCidr,
+    RuleType::SrcPort,
+    RuleType::Inbound,
+    RuleType::And,
+    RuleType::Or,
+    RuleType::Not,
+    RuleType::DomainRegex,
+    RuleType::ProcessName,
+    RuleType::ProcessPath,
+    RuleType::Uid,
+];
+
+// 将规则编码为二进制格式，规则的出站不会写入
+pub fn encode_binary(rule_vec: &[Rule]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(BINARY_MAGIC);
+    buffer.push(BINARY_VERSION);
+    write_varint(&mut buffer, rule_vec.len());
+    for rule in rule_vec {
+        encode_rule(&mut buffer, rule);
+    }
+    buffer
+}
+
+fn encode_rule(buffer: &mut Vec<u8>, rule: &Rule) {
+    let code = RULE_TYPE_LIST
+        .iter()
+        .position(|r#type| *r#type == rule.r#type)
+        .unwrap_or_default();
+    buffer.push(code as u8);
+    buffer.push(rule.resolve as u8);
+    write_varint(buffer, rule.source.len());
+    for source in &rule.source {
+        write_varint(buffer, source.len());
+        buffer.extend_from_slice(source.as_bytes());
+    }
+    write_varint(buffer, rule.rule.len());
+    for rule in &rule.rule {
+        encode_rule(buffer, rule);
+    }
+}
+
+fn decode_binary(content: &[u8]) -> Result<Vec<RuleSetRule>> {
+    let mut reader = BinaryReader { content, pos: 0 };
+    ensure!(reader.bytes(4)? == BINARY_MAGIC, "不是二进制规则集文件");
+    let version = reader.u8()?;
+    ensure!(
+        version == BINARY_VERSION,
+        "不支持的二进制规则集版本: {}",
+        version
+    );
+    let count = reader.varint()?;
+    let mut rule_vec = Vec::new();
+    for _ in 0..count {
+        let rule = reader.rule(0)?;
+        rule_vec.push(RuleSetRule {
+            r#type: rule.r#type,
+            source: rule.source,
+            resolve: rule.resolve,
+            rule: rule.rule,
+        });
+    }
+    ensure!(reader.pos == content.len(), "二进制规则集末尾有多余的数据");
+    Ok(rule_vec)
+}
+
+// 子规则的最大嵌套深度，防止损坏的文件导致栈溢出
+const MAX_DEPTH: usize = 32;
+
+struct BinaryReader<'a> {
+    content: &'a [u8],
+    pos: usize,
+}
+
+impl BinaryReader<'_> {
+    fn rule(&mut self, depth: usize) -> Result<Rule> {
+        ensure!(depth < MAX_DEPTH, "二进制规则集嵌套过深");
+        let code = self.u8()?;
+        let r#type = *RULE_TYPE_LIST
+            .get(code as usize)
+            .ok_or_else(|| anyhow!("未知的规则类型编号: {}", code))?;
+        let resolve = self.u8()? != 0;
+        let mut source = Vec::new();
+        for _ in 0..self.varint()? {
+            let len = self.varint()?;
+            let bytes = self.bytes(len)?;
+            source
+                .push(String::from_utf8(bytes.to_vec()).context("规则条目不是有效的 UTF-8 文本")?);
+        }
+        let mut rule = Vec::new();
+        for _ in 0..self.varint()? {
+            rule.push(self.rule(depth + 1)?);
+        }
+        Ok(Rule {
+            r#type,
+            source,
+            outbound: String::new(),
+            resolve,
+            rule,
+            rule_set: None,
+        })
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.content.len())
+            .ok_or_else(|| anyhow!("二进制规则集数据不完整"))?;
+        let bytes = &self.content[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // 无符号 LEB128
+    fn varint(&mut self) -> Result<usize> {
+        let mut value: usize = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("无效的 varint")
+    }
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classical() {
+        let content = b"payload:\n\
+            - DOMAIN-SUFFIX,a.com\n\
+            - DOMAIN-SUFFIX,b.com\n\
+            - IP-CIDR,10.0.0.0/8,no-resolve\n\
+            - IP-CIDR,192.168.0.0/16\n\
+            - USER-AGENT,curl*\n\
+            - DST-PORT,443\n";
+        let rule_vec = decode_classical(content).unwrap();
+        let summary: Vec<_> = rule_vec
+            .iter()
+            .map(|rule| (rule.r#type, rule.source.join(" "), rule.resolve))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (RuleType::DomainSuffix, "a.com b.com".to_string(), false),
+                (RuleType::IpCidr, "10.0.0.0/8".to_string(), false),
+                (RuleType::IpCidr, "192.168.0.0/16".to_string(), true),
+                (RuleType::DstPort, "443".to_string(), false),
+            ]
+        );
+    }
+
+    #[test]
+    fn text() {
+        let content = b"# comment\nFoo.com\n+.bar.com\n\n.baz.com\n*.qux.com\n";
+        let rule_vec = decode_text(content).unwrap();
+        let summary: Vec<_> = rule_vec
+            .iter()
+            .map(|rule| (rule.r#type, rule.source.clone()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (RuleType::DomainFull, vec!["foo.com".to_string()]),
+                (RuleType::DomainSuffix, vec!["bar.com".to_string()]),
+                (
+                    RuleType::DomainRegex,
+                    vec![
+                        "^.+\\.baz\\.com$".to_string(),
+                        "^[^.]+\\.qux\\.com$".to_string()
+                    ]
+                ),
+            ]
+        );
+        assert!(decode_text(b"# comment\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let content = b"\
+- type: domain_suffix
+  source: [a.com, b.com]
+- type: ip_cidr
+  source: [10.0.0.0/8]
+  resolve: true
+- type: and
+  rule:
+    - type: dst_port
+      source: [\"443\"]
+    - type: not
+      rule:
+        - type: uid
+          source: [\"1000\"]
+";
+        let rule_vec = RuleSetFormat::Yaml
+            .decode(content, "test", "direct")
+            .unwrap();
+        let decoded: Vec<Rule> = decode_binary(&encode_binary(&rule_vec))
+            .unwrap()
+            .into_iter()
+            .map(|rule| rule.to_rule("test", "direct"))
+            .collect();
+        assert_eq!(format!("{:?}", decoded), format!("{:?}", rule_vec));
+        assert!(decode_binary(&encode_binary(&rule_vec)[..20]).is_err());
+    }
+
+    #[test]
+    fn classical_invalid_entry_index() {
+        let content = b"payload:\n\
+            - IP-CIDR,10.0.0.0/8\n\
+            - IP-CIDR,172.16.0.0/12\n\
+            - IP-CIDR,192.168.0.0/33\n";
+        let error = decode_classical(content).unwrap_err();
+        assert!(
+            error.to_string().starts_with("第 3 条规则无效"),
+            "{:#}",
+            error
+        );
+    }
+}
@@ -1,9 +1,26 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use tracing::error;
-use x_proxy::{config::Config, service::ServiceConfig};
+use x_proxy::{
+    config::{
+        Config,
+        rule_set::{RuleSetFormat, encode_binary},
+    },
+    protocol::address::Address,
+    service::{ServiceConfig, route::RouteContext},
+};
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    // 子命令
+    let arg_vec: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = arg_vec.first() {
+        return match command.as_str() {
+            "compile" => compile(&arg_vec[1..]),
+            "explain" => explain(&arg_vec[1..]).await,
+            _ => bail!("未知的子命令: {}", command),
+        };
+    }
+
     // 加载配置文件
     let config = Config::load()?;
 
@@ -22,3 +39,41 @@ async fn main() -> Result<()> {
 
     Ok(())
 }
+
+// 将规则集预编译为二进制格式: x-proxy compile <yaml|classical|text> <输入文件> <输出文件>
+fn compile(arg_vec: &[String]) -> Result<()> {
+    let [format, input, output] = arg_vec else {
+        bail!("用法: x-proxy compile <yaml|classical|text> <输入文件> <输出文件>");
+    };
+    // 输出解析时被忽略的规则
+    tracing_subscriber::fmt().init();
+    let format = RuleSetFormat::parse(format)?;
+    let content = std::fs::read(input).with_context(|| format!("读取规则集失败: {}", input))?;
+    let rule_vec = format
+        .decode(&content, input, "")
+        .with_context(|| format!("解析规则集失败: {}", input))?;
+    for rule in &rule_vec {
+        rule.validate()?;
+    }
+    std::fs::write(output, encode_binary(&rule_vec))
+        .with_context(|| format!("写入规则集失败: {}", output))?;
+    println!("已编译 {} 条规则: {} -> {}", rule_vec.len(), input, output);
+    Ok(())
+}
+
+// 查询目标会经由哪个出站以及原因，不发送流量: x-proxy explain <host:port> [入站名]
+async fn explain(arg_vec: &[String]) -> Result<()> {
+    let (target, inbound) = match arg_vec {
+        [target] => (target, None),
+        [target, inbound] => (target, Some(inbound)),
+        _ => bail!("用法: x-proxy explain <host:port> [入站名]"),
+    };
+    let config = Config::load()?;
+    ServiceConfig::load(&config)?;
+    let mut context = RouteContext::new(Address::parse(target, None)?);
+    if let Some(inbound) = inbound {
+        context = context.with_inbound(inbound);
+    }
+    println!("{}", ServiceConfig::get()?.explain(context).await);
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, SocketAddr},
+};
+
+use anyhow::{Result, anyhow};
+
+// 代理请求的目标地址，可能是 IP 地址或者尚未解析的域名。
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Address {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl Address {
+    // 解析 "host:port" / "[v6]:port" 形式的地址，缺少端口时使用 default_port。
+    pub fn parse(s: &str, default_port: Option<u16>) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Address::Ip(addr));
+        }
+
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            // IPv6 字面量: [::1] 或 [::1]:443
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("无效地址: {}", s))?;
+            let port = match rest.strip_prefix(':') {
+                Some(port) => Some(port),
+                None if rest.is_empty() => None,
+                None => return Err(anyhow!("无效地址: {}", s)),
+            };
+            (host, port)
+        } else {
+            match s.rsplit_once(':') {
+                Some((host, port)) if !host.contains(':') => (host, Some(port)),
+                _ => (s, None),
+            }
+        };
+
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| anyhow!("无效端口: {}", s))?,
+            None => default_port.ok_or_else(|| anyhow!("地址缺少端口: {}", s))?,
+        };
+
+        if host.is_empty() {
+            return Err(anyhow!("地址缺少主机名: {}", s));
+        }
+
+        match host.parse::<IpAddr>() {
+            Ok(ip) => Ok(Address::Ip(SocketAddr::new(ip, port))),
+            Err(_) => Ok(Address::Domain(host.to_string(), port)),
+        }
+    }
+
+    pub fn host(&self) -> String {
+        match self {
+            Address::Ip(addr) => addr.ip().to_string(),
+            Address::Domain(domain, _) => domain.clone(),
+        }
+    }
+
+    pub fn port(&self) -> u16 {
+        match self {
+            Address::Ip(addr) => addr.port(),
+            Address::Domain(_, port) => *port,
+        }
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Address::Ip(addr) => write!(f, "{}", addr),
+            Address::Domain(domain, port) => write!(f, "{}:{}", domain, port),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+pub mod outbound;
+
+use serde::Deserialize;
+
+// 直连出站: 不经过任何上游，直接连接目标地址
+#[derive(Debug, Deserialize, Clone)]
+pub struct Direct {
+    pub name: String,
+}
@@ -0,0 +1,91 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex};
+
+use anyhow::{Result, anyhow};
+use bytes::{Bytes, BytesMut};
+use tokio::net::{TcpStream, UdpSocket, lookup_host};
+use tracing::debug;
+
+use super::Direct;
+use crate::protocol::{address::Address, udp::UDP_BUFFER_SIZE};
+
+impl Direct {
+    pub async fn connect(&self, target: &Address) -> Result<TcpStream> {
+        let stream = match target {
+            Address::Ip(addr) => TcpStream::connect(addr).await?,
+            Address::Domain(domain, port) => TcpStream::connect((domain.as_str(), *port)).await?,
+        };
+        debug!("出站 {} 直连 {} 成功", self.name, target);
+        Ok(stream)
+    }
+
+    pub async fn bind_udp(&self) -> Result<DirectDatagram> {
+        let v4 = UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await?;
+        // 没有 IPv6 的主机上绑定会失败，此时只支持 IPv4 目标
+        let v6 = UdpSocket::bind(SocketAddr::from(([0u16; 8], 0))).await.ok();
+        Ok(DirectDatagram {
+            v4,
+            v6,
+            resolved_hash_map: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+// 直接收发 UDP 数据报，IPv4 和 IPv6 目标分别使用独立的套接字
+pub struct DirectDatagram {
+    v4: UdpSocket,
+    v6: Option<UdpSocket>,
+    // 域名目标的解析结果，同一个关联内只解析一次
+    resolved_hash_map: Mutex<HashMap<String, SocketAddr>>,
+}
+
+impl DirectDatagram {
+    pub async fn send_to(&self, payload: &[u8], target: &Address) -> Result<()> {
+        let addr = self.resolve(target).await?;
+        let socket = match (addr, &self.v6) {
+            (SocketAddr::V4(_), _) => &self.v4,
+            (SocketAddr::V6(_), Some(v6)) => v6,
+            (SocketAddr::V6(_), None) => return Err(anyhow!("不支持 IPv6 目标: {}", target)),
+        };
+        socket.send_to(payload, addr).await?;
+        Ok(())
+    }
+
+    pub async fn recv_from(&self) -> Result<(Bytes, Address)> {
+        let mut buffer = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+        let (_, source) = match &self.v6 {
+            Some(v6) => {
+                let mut buffer_v6 = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+                tokio::select! {
+                    result = self.v4.recv_buf_from(&mut buffer) => result?,
+                    result = v6.recv_buf_from(&mut buffer_v6) => {
+                        buffer = buffer_v6;
+                        result?
+                    }
+                }
+            }
+            None => self.v4.recv_buf_from(&mut buffer).await?,
+        };
+        Ok((buffer.freeze(), Address::Ip(source)))
+    }
+
+    async fn resolve(&self, target: &Address) -> Result<SocketAddr> {
+        let domain = match target {
+            Address::Ip(addr) => return Ok(*addr),
+            Address::Domain(domain, _) => domain,
+        };
+
+        if let Some(addr) = self.resolved_hash_map.lock().unwrap().get(domain) {
+            return Ok(SocketAddr::new(addr.ip(), target.port()));
+        }
+
+        let addr = lookup_host((domain.as_str(), target.port()))
+            .await?
+            .find(|addr| addr.is_ipv4() || self.v6.is_some())
+            .ok_or_else(|| anyhow!("无法解析域名: {}", domain))?;
+        self.resolved_hash_map
+            .lock()
+            .unwrap()
+            .insert(domain.clone(), addr);
+        Ok(addr)
+    }
+}
@@ -43,6 +43,12 @@ pub struct DnsHeader {
     pub additional_entries: u16,
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsHeader {
     // 创建一个默认的 DNS 头部实例。
     pub fn new() -> Self {
@@ -454,7 +460,7 @@ impl DnsRecord {
         // 计算数据部分的实际长度
         let data_len = buffer.len() - pos - 2;
         // 回到之前的位置，写入正确的长度
-        let mut len_bytes = (data_len as u16).to_be_bytes();
+        let len_bytes = (data_len as u16).to_be_bytes();
         buffer[pos..pos + 2].copy_from_slice(&len_bytes);
 
         Ok(())
@@ -475,6 +481,12 @@ pub struct DnsPacket {
     pub resources: Vec<DnsRecord>,
 }
 
+impl Default for DnsPacket {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsPacket {
     // 创建一个空的 DNS 数据包。
     pub fn new() -> Self {
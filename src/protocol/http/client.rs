@@ -0,0 +1,280 @@
+use std::{collections::HashMap, io::ErrorKind, sync::Arc, time::Duration};
+
+use anyhow::{Context, Result, bail, ensure};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
+    time::timeout,
+};
+use tokio_rustls::{
+    TlsConnector,
+    rustls::{ClientConfig, RootCertStore, crypto::ring, pki_types::ServerName},
+};
+use tracing::debug;
+
+use super::{
+    model::{RequestHead, ResponseHead},
+    read_head, split_authority,
+};
+use crate::{
+    config::outbound::Outbound,
+    protocol::{address::Address, stream::ProxyStream},
+};
+
+// 响应体的最大长度
+const MAX_BODY_SIZE: usize = 64 * 1024 * 1024;
+
+// 最多跟随的重定向次数
+const MAX_REDIRECT: usize = 5;
+
+// 单次请求 (连接、TLS 握手、读取头部和响应体) 的最长时间，服务端停止响应时按失败处理
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+// 经由出站发送 GET 请求并返回响应体，支持 http 和 https，用于下载远程规则集等资源
+pub async fn get(url: &str, outbound: &Outbound) -> Result<Bytes> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECT {
+        let (response_head, body) = timeout(REQUEST_TIMEOUT, request(&url, outbound))
+            .await
+            .with_context(|| format!("请求 {} 超时", url))??;
+        match response_head.status {
+            200 => return Ok(body),
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response_head
+                    .headers
+                    .get("location")
+                    .with_context(|| format!("{} 重定向缺少 Location", url))?;
+                let location = resolve_url(&url, location);
+                debug!("{} 重定向到 {}", url, location);
+                url = location;
+            }
+            status => bail!("请求 {} 失败: 状态码 {}", url, status),
+        }
+    }
+    bail!("请求 {} 失败: 重定向次数过多", url)
+}
+
+async fn request(url: &str, outbound: &Outbound) -> Result<(ResponseHead, Bytes)> {
+    let (tls, target, authority, path) = parse_url(url)?;
+    let stream = outbound.connect(&target).await?;
+    let mut stream: ProxyStream = if tls {
+        let server_name = ServerName::try_from(target.host())?;
+        Box::new(tls_connector()?.connect(server_name, stream).await?)
+    } else {
+        stream
+    };
+
+    let request_head = RequestHead {
+        method: "GET".to_string(),
+        uri: path,
+        version: "HTTP/1.1".to_string(),
+        headers: HashMap::from([
+            ("host".to_string(), authority),
+            ("user-agent".to_string(), "x-proxy".to_string()),
+            ("accept".to_string(), "*/*".to_string()),
+            ("connection".to_string(), "close".to_string()),
+        ]),
+    };
+    stream
+        .write_all(&RequestHead::encode(&request_head)?)
+        .await?;
+
+    let mut buffer = BytesMut::new();
+    let head = read_head(&mut stream, &mut buffer).await?;
+    let response_head = ResponseHead::decode(&head)?;
+    if response_head.status != 200 {
+        return Ok((response_head, Bytes::new()));
+    }
+
+    let chunked = response_head
+        .headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+    let length = response_head
+        .headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok());
+    let body = if chunked {
+        read_chunked(&mut stream, buffer).await?
+    } else if let Some(length) = length {
+        ensure!(length <= MAX_BODY_SIZE, "响应体过大: {}", length);
+        while buffer.len() < length {
+            let n = stream.read_buf(&mut buffer).await?;
+            ensure!(n != 0, "读取响应体时连接已关闭");
+        }
+        buffer.truncate(length);
+        buffer.freeze()
+    } else {
+        read_to_end(&mut stream, buffer).await?
+    };
+    Ok((response_head, body))
+}
+
+// 解析 URL，返回 (是否使用 TLS, 目标地址, Host 头部, 源站形式的路径)
+fn parse_url(url: &str) -> Result<(bool, Address, String, String)> {
+    let (tls, rest) = match url.split_once("://") {
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+        Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+        _ => bail!("不支持的 URL: {}", url),
+    };
+    let (authority, path) = split_authority(rest);
+    let target = Address::parse(authority, Some(if tls { 443 } else { 80 }))?;
+    Ok((tls, target, authority.to_string(), path))
+}
+
+// 将 Location 解析为绝对 URL，支持绝对 URL、省略协议的 "//host/path"、绝对路径和相对路径
+fn resolve_url(base: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let (scheme, rest) = base.split_once("://").unwrap_or(("http", base));
+    if let Some(location) = location.strip_prefix("//") {
+        return format!("{}://{}", scheme, location);
+    }
+    let (authority, path) = split_authority(rest);
+    if location.starts_with('/') {
+        return format!("{}://{}{}", scheme, authority, location);
+    }
+    // 相对路径以请求路径的目录为基准，查询串替换原查询串
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    if location.starts_with('?') {
+        return format!("{}://{}{}{}", scheme, authority, path, location);
+    }
+    let directory = path.rfind('/').map_or("/", |pos| &path[..=pos]);
+    format!("{}://{}{}{}", scheme, authority, directory, location)
+}
+
+fn tls_connector() -> Result<TlsConnector> {
+    let root_store = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+// 读取到连接关闭，服务端没有发送 TLS close_notify 也视为正常结束
+async fn read_to_end<R: AsyncRead + Unpin>(reader: &mut R, mut buffer: BytesMut) -> Result<Bytes> {
+    loop {
+        ensure!(buffer.len() <= MAX_BODY_SIZE, "响应体过大");
+        match reader.read_buf(&mut buffer).await {
+            Ok(0) => return Ok(buffer.freeze()),
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(buffer.freeze()),
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+// 解码分块传输的响应体: 每块为 "<十六进制长度>\r\n<数据>\r\n"，长度为 0 的块表示结束
+async fn read_chunked<R: AsyncRead + Unpin>(reader: &mut R, mut buffer: BytesMut) -> Result<Bytes> {
+    let mut body = BytesMut::new();
+    loop {
+        let line = read_line(reader, &mut buffer).await?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size =
+            usize::from_str_radix(size, 16).with_context(|| format!("无效的分块长度: {}", size))?;
+        if size == 0 {
+            return Ok(body.freeze());
+        }
+        let length = body
+            .len()
+            .checked_add(size)
+            .with_context(|| format!("分块长度溢出: {}", size))?;
+        ensure!(length <= MAX_BODY_SIZE, "响应体过大");
+        while buffer.len() < size + 2 {
+            let n = reader.read_buf(&mut buffer).await?;
+            ensure!(n != 0, "读取分块时连接已关闭");
+        }
+        body.extend_from_slice(&buffer.split_to(size));
+        let _ = buffer.split_to(2);
+    }
+}
+
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut R, buffer: &mut BytesMut) -> Result<String> {
+    loop {
+        if let Some(pos) = buffer.windows(2).position(|window| window == b"\r\n") {
+            let line = buffer.split_to(pos + 2);
+            return Ok(String::from_utf8_lossy(&line[..pos]).to_string());
+        }
+        ensure!(buffer.len() < 1024, "分块长度行过长");
+        let n = reader.read_buf(buffer).await?;
+        ensure!(n != 0, "读取分块时连接已关闭");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // 启动本地 HTTP 服务，按顺序对每个连接返回一个响应
+    async fn serve(response_vec: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for response in response_vec {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = BytesMut::new();
+                read_head(&mut stream, &mut buffer).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn relative_redirect_and_chunked() {
+        let base = serve(vec![
+            "HTTP/1.1 302 Found\r\nLocation: rule.txt\r\nContent-Length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             6\r\nDOMAIN\r\n8;ext=1\r\n,foo.com\r\n0\r\n\r\n",
+        ])
+        .await;
+        let body = get(&format!("{}/dir/list?v=1", base), &Outbound::direct())
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"DOMAIN,foo.com");
+    }
+
+    #[tokio::test]
+    async fn chunk_size_overflow() {
+        let base = serve(vec![
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+             1\r\na\r\nffffffffffffffff\r\n",
+        ])
+        .await;
+        assert!(get(&base, &Outbound::direct()).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_server_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        // 接受连接后不发送任何响应
+        let server = tokio::spawn(async move { listener.accept().await });
+        let error = get(&format!("http://{}/", addr), &Outbound::direct())
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("超时"), "{:#}", error);
+        server.abort();
+    }
+
+    #[test]
+    fn resolve_location() {
+        let base = "https://example.com/a/b?x=1";
+        assert_eq!(
+            resolve_url(base, "http://other.org/c"),
+            "http://other.org/c"
+        );
+        assert_eq!(resolve_url(base, "//cdn.org/c"), "https://cdn.org/c");
+        assert_eq!(resolve_url(base, "/c"), "https://example.com/c");
+        assert_eq!(resolve_url(base, "c"), "https://example.com/a/c");
+        assert_eq!(resolve_url(base, "?y=2"), "https://example.com/a/b?y=2");
+        assert_eq!(
+            resolve_url("http://example.com", "c"),
+            "http://example.com/c"
+        );
+    }
+}
@@ -1,10 +1,22 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use anyhow::Result;
-use tokio::net::{TcpListener, TcpStream};
+use anyhow::{Result, bail};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncWriteExt, copy, copy_bidirectional, split},
+    net::{TcpListener, TcpStream},
+};
 use tracing::{debug, info, warn};
 
-use super::Http;
+use super::{
+    Http,
+    model::{RequestHead, ResponseHead},
+    read_head, split_authority,
+};
+use crate::{
+    protocol::{address::Address, reject::RejectError, sniff::sniff_stream},
+    service::{ServiceConfig, route::RouteContext},
+};
 
 impl Http {
     pub async fn listen(&self) -> Result<()> {
@@ -31,17 +43,267 @@ impl Http {
     }
 
     pub async fn accept(&self, mut stream: TcpStream, addr: SocketAddr) -> Result<()> {
-        // 处理连接
-        // 传递连接
-
-        let mut buffer = [0u8; 1024];
-        let n = stream.peek(&mut buffer).await?;
+        let mut buffer = BytesMut::new();
+        let head = read_head(&mut stream, &mut buffer).await?;
+        let request_head = RequestHead::decode(&head)?;
         debug!(
-            "接收到 {} 字节数据 {}",
-            n,
-            String::from_utf8_lossy(&buffer[..n])
+            "来自 {} 的请求: {} {}",
+            addr, request_head.method, request_head.uri
         );
 
+        if request_head.method.eq_ignore_ascii_case("CONNECT") {
+            self.handle_connect(stream, addr, request_head, buffer)
+                .await
+        } else {
+            self.handle_forward(stream, addr, request_head, head, buffer)
+                .await
+        }
+    }
+
+    // 处理 CONNECT 隧道: 连接出站后回复 200，然后双向转发数据。
+    // 嗅探时需要先回复 200，客户端才会发送隧道内的首个数据包
+    async fn handle_connect(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        request_head: RequestHead,
+        mut buffer: BytesMut,
+    ) -> Result<()> {
+        let target = match Address::parse(&request_head.uri, None) {
+            Ok(target) => target,
+            Err(e) => {
+                stream.write_all(&error_response(400)?).await?;
+                return Err(e);
+            }
+        };
+
+        let service = ServiceConfig::get()?;
+        let sniff = self.sniff && matches!(target, Address::Ip(_));
+        let mut context = RouteContext::new(target.clone())
+            .with_source(addr)
+            .with_inbound(&self.name);
+        if sniff {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?;
+            let domain = sniff_stream(&mut stream, &mut buffer).await;
+            context = context.with_sniffed(domain, self.sniff_override_destination);
+        }
+        let mut outbound_stream = match service.connect(context).await {
+            Ok(outbound_stream) => outbound_stream,
+            // 已经回复 200 时只能直接关闭连接
+            Err(e) if sniff => {
+                if let Some(reject) = e.downcast_ref::<RejectError>() {
+                    debug!("{}", reject);
+                    return Ok(());
+                }
+                return Err(e);
+            }
+            Err(e) => return connect_failed(&mut stream, e).await,
+        };
+
+        if !sniff {
+            stream
+                .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+                .await?;
+        }
+
+        // 客户端可能在收到 200 之前就已经发送了隧道内的数据，嗅探时读取的数据也在其中
+        if !buffer.is_empty() {
+            outbound_stream.write_all(&buffer).await?;
+        }
+
+        copy_bidirectional(&mut stream, &mut outbound_stream).await?;
         Ok(())
     }
+
+    // 处理普通 HTTP 代理请求: 将绝对 URI 改写为源站形式后经出站转发，再把响应流式返回。
+    // 为了不解析请求体和响应体的边界，每个连接只转发一个请求，并强制双方使用 Connection: close
+    async fn handle_forward(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        request_head: RequestHead,
+        head: Bytes,
+        buffer: BytesMut,
+    ) -> Result<()> {
+        let (target, authority, path) = match parse_absolute_uri(&request_head.uri) {
+            Ok(parts) => parts,
+            Err(e) => {
+                stream.write_all(&error_response(400)?).await?;
+                return Err(e);
+            }
+        };
+
+        let service = ServiceConfig::get()?;
+        let context = RouteContext::new(target.clone())
+            .with_source(addr)
+            .with_inbound(&self.name);
+        let mut outbound_stream = match service.connect(context).await {
+            Ok(outbound_stream) => outbound_stream,
+            Err(e) => return connect_failed(&mut stream, e).await,
+        };
+
+        outbound_stream
+            .write_all(&rewrite_request_head(
+                &head,
+                &request_head,
+                &authority,
+                &path,
+            ))
+            .await?;
+        if !buffer.is_empty() {
+            outbound_stream.write_all(&buffer).await?;
+        }
+
+        let (mut client_read, mut client_write) = split(stream);
+        let (mut server_read, mut server_write) = split(outbound_stream);
+
+        // 请求体由单独的任务转发，响应结束后即可取消
+        let request_task = tokio::spawn(async move {
+            copy(&mut client_read, &mut server_write).await?;
+            server_write.shutdown().await
+        });
+
+        let result = async {
+            let mut buffer = BytesMut::new();
+            let head = read_head(&mut server_read, &mut buffer).await?;
+            let response_head = ResponseHead::decode(&head)?;
+            debug!("{} 响应状态: {}", target, response_head.status);
+
+            client_write
+                .write_all(&rewrite_response_head(&head))
+                .await?;
+            client_write.write_all(&buffer).await?;
+            copy(&mut server_read, &mut client_write).await?;
+            client_write.shutdown().await?;
+            Ok(())
+        }
+        .await;
+
+        request_task.abort();
+        result
+    }
+}
+
+// 逐跳头部，只对单个连接有效，代理转发时需要移除
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "connection",
+    "proxy-connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "upgrade",
+];
+
+// 解析绝对 URI，返回 (目标地址, Host 头部, 源站形式的路径)
+fn parse_absolute_uri(uri: &str) -> Result<(Address, String, String)> {
+    let rest = match uri.get(..7) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("http://") => &uri[7..],
+        _ => bail!("不支持的请求 URI: {}", uri),
+    };
+
+    let (authority, path) = split_authority(rest);
+
+    // 去掉 URI 中可能携带的用户信息
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let target = Address::parse(authority, Some(80))?;
+
+    Ok((target, authority.to_string(), path))
+}
+
+// 改写请求头部: 请求行使用源站形式的路径，Host 替换为绝对 URI 中的主机 (RFC 7230 5.4)，
+// 移除逐跳头部并追加 Connection: close。与响应一样按行处理，保留重复的 Cookie 等头部
+fn rewrite_request_head(
+    head: &[u8],
+    request_head: &RequestHead,
+    authority: &str,
+    path: &str,
+) -> Bytes {
+    let head = String::from_utf8_lossy(head);
+    let mut buffer = Vec::new();
+    let request_line = format!(
+        "{} {} {}\r\n",
+        request_head.method, path, request_head.version
+    );
+    buffer.extend_from_slice(request_line.as_bytes());
+    buffer.extend_from_slice(format!("Host: {}\r\n", authority).as_bytes());
+    for (name, line) in header_lines(&head) {
+        if name == "host" {
+            continue;
+        }
+        buffer.extend_from_slice(line.as_bytes());
+        buffer.extend_from_slice(b"\r\n");
+    }
+    buffer.extend_from_slice(b"Connection: close\r\n\r\n");
+    Bytes::from(buffer)
+}
+
+// 改写响应头部: 移除逐跳头部并追加 Connection: close。
+// 这里按行处理原始头部，避免 ResponseHead 合并重复的 Set-Cookie 等头部
+fn rewrite_response_head(head: &[u8]) -> Bytes {
+    let head = String::from_utf8_lossy(head);
+    let status_line = head.split("\r\n").next().unwrap_or_default();
+
+    let mut buffer = Vec::new();
+    buffer.extend_from_slice(status_line.as_bytes());
+    buffer.extend_from_slice(b"\r\n");
+    for (_, line) in header_lines(&head) {
+        buffer.extend_from_slice(line.as_bytes());
+        buffer.extend_from_slice(b"\r\n");
+    }
+    buffer.extend_from_slice(b"Connection: close\r\n\r\n");
+    Bytes::from(buffer)
+}
+
+// 按顺序返回头部中需要转发的 (小写头部名, 原始行)，
+// 跳过首行、逐跳头部，以及 Connection 头部中列出的头部
+fn header_lines(head: &str) -> Vec<(String, &str)> {
+    let mut listed = Vec::new();
+    let mut header_lines = Vec::new();
+    for line in head.split("\r\n").skip(1).filter(|line| !line.is_empty()) {
+        let name = line
+            .split_once(':')
+            .map_or(line, |(name, _)| name)
+            .trim()
+            .to_lowercase();
+        if name == "connection"
+            && let Some((_, value)) = line.split_once(':')
+        {
+            listed.extend(value.split(',').map(|name| name.trim().to_lowercase()));
+        }
+        header_lines.push((name, line));
+    }
+    header_lines
+        .retain(|(name, _)| !HOP_BY_HOP_HEADERS.contains(&name.as_str()) && !listed.contains(name));
+    header_lines
+}
+
+// 连接出站失败时回复客户端: 被拒绝出站拦截时返回 403 (blackhole 不回复)，其它错误返回 502
+async fn connect_failed(stream: &mut TcpStream, error: anyhow::Error) -> Result<()> {
+    if let Some(reject) = error.downcast_ref::<RejectError>() {
+        if !reject.silent {
+            stream.write_all(&error_response(403)?).await?;
+        }
+        debug!("{}", reject);
+        return Ok(());
+    }
+    stream.write_all(&error_response(502)?).await?;
+    Err(error)
+}
+
+// 构造一个不带响应体的错误响应
+fn error_response(status: u16) -> Result<Bytes> {
+    let mut headers = HashMap::new();
+    headers.insert("content-length".to_string(), "0".to_string());
+    headers.insert("connection".to_string(), "close".to_string());
+    ResponseHead::encode(&ResponseHead {
+        status,
+        version: "HTTP/1.1".to_string(),
+        headers,
+    })
 }
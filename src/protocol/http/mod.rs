@@ -1,12 +1,60 @@
+pub mod client;
 pub mod inbound;
 pub mod model;
 pub mod outbound;
 
+use anyhow::{Result, ensure};
+use bytes::{Bytes, BytesMut};
 use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Http {
     pub name: String,
     pub host: String,
     pub port: u16,
+    // 出站: 连接上游时使用的用户名和密码 (Proxy-Authorization: Basic)
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // 入站: 嗅探 CONNECT 隧道内的 TLS SNI 或 HTTP Host，用于按域名路由 IP 目标
+    #[serde(default)]
+    pub sniff: bool,
+    // 入站: 连接嗅探到的域名而不是客户端请求的 IP
+    #[serde(default)]
+    pub sniff_override_destination: bool,
+}
+
+// 拆分 URL 中协议之后的部分，返回 (authority, 源站形式的路径)。
+// 没有路径时为 "/"，只有查询串时补上 "/"
+pub fn split_authority(rest: &str) -> (&str, String) {
+    match rest.find(['/', '?']) {
+        Some(pos) if rest[pos..].starts_with('?') => (&rest[..pos], format!("/{}", &rest[pos..])),
+        Some(pos) => (&rest[..pos], rest[pos..].to_string()),
+        None => (rest, "/".to_string()),
+    }
+}
+
+// HTTP 头部的最大长度，防止恶意客户端无限发送头部
+const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+// 从连接中读取一个完整的 HTTP 头部 (直到 "\r\n\r\n")。
+// 头部之后多读到的字节会保留在 buffer 中，由调用方继续处理。
+pub async fn read_head<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut BytesMut,
+) -> Result<Bytes> {
+    let mut searched = 0;
+    loop {
+        if let Some(pos) = buffer[searched..]
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            return Ok(buffer.split_to(searched + pos + 4).freeze());
+        }
+        searched = buffer.len().saturating_sub(3);
+
+        ensure!(buffer.len() < MAX_HEAD_SIZE, "HTTP 头部过大");
+        let n = reader.read_buf(buffer).await?;
+        ensure!(n != 0, "读取 HTTP 头部时连接已关闭");
+    }
 }
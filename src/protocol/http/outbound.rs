@@ -1,12 +1,77 @@
+use std::{collections::HashMap, fmt::Display};
+
 use anyhow::Result;
-use tokio::net::TcpStream;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::BytesMut;
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tracing::debug;
 
-use super::Http;
+use super::{
+    Http,
+    model::{RequestHead, ResponseHead},
+    read_head,
+};
+use crate::protocol::{address::Address, stream::PrefixedStream};
 
 impl Http {
-    pub async fn connect(&self) -> Result<TcpStream> {
+    // 通过上游 HTTP 代理的 CONNECT 隧道连接目标地址。
+    // 响应头部之后多读到的数据会保留在返回的连接中
+    pub async fn connect(&self, target: &Address) -> Result<PrefixedStream<TcpStream>> {
         let addr = format!("{}:{}", self.host, self.port);
-        let stream = TcpStream::connect(addr).await?;
-        Ok(stream)
+        let mut stream = TcpStream::connect(addr).await?;
+
+        let authority = target.to_string();
+        let mut headers = HashMap::new();
+        headers.insert("host".to_string(), authority.clone());
+        if let Some(username) = &self.username {
+            let password = self.password.as_deref().unwrap_or_default();
+            let credentials = STANDARD.encode(format!("{}:{}", username, password));
+            headers.insert(
+                "proxy-authorization".to_string(),
+                format!("Basic {}", credentials),
+            );
+        }
+        let request_head = RequestHead {
+            method: "CONNECT".to_string(),
+            uri: authority,
+            version: "HTTP/1.1".to_string(),
+            headers,
+        };
+        stream
+            .write_all(&RequestHead::encode(&request_head)?)
+            .await?;
+
+        let mut buffer = BytesMut::new();
+        let head = read_head(&mut stream, &mut buffer).await?;
+        let response_head = ResponseHead::decode(&head)?;
+        if !(200..300).contains(&response_head.status) {
+            return Err(ConnectError {
+                outbound: self.name.clone(),
+                status: response_head.status,
+            }
+            .into());
+        }
+        debug!("出站 {} 连接 {} 成功", self.name, target);
+
+        Ok(PrefixedStream::new(buffer.freeze(), stream))
+    }
+}
+
+// 上游 HTTP 代理拒绝 CONNECT 请求
+#[derive(Debug)]
+pub struct ConnectError {
+    pub outbound: String,
+    pub status: u16,
+}
+
+impl Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "出站 {} 拒绝 CONNECT 请求, 状态码: {}",
+            self.outbound, self.status
+        )
     }
 }
+
+impl std::error::Error for ConnectError {}
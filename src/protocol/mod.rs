@@ -1,6 +1,12 @@
+pub mod address;
+pub mod direct;
 pub mod dns;
 pub mod http;
+pub mod reject;
+pub mod sniff;
 pub mod socks5;
+pub mod stream;
+pub mod udp;
 
 pub enum Protocol {
     Http(http::Http),
@@ -0,0 +1,35 @@
+use std::fmt::Display;
+
+use anyhow::Result;
+use serde::Deserialize;
+
+// 拒绝出站: reject 向客户端返回协议对应的拒绝应答，blackhole 直接关闭连接
+#[derive(Debug, Deserialize, Clone)]
+pub struct Reject {
+    pub name: String,
+}
+
+impl Reject {
+    pub fn reject<T>(&self, silent: bool) -> Result<T> {
+        Err(RejectError {
+            outbound: self.name.clone(),
+            silent,
+        }
+        .into())
+    }
+}
+
+// 连接被拒绝出站拦截，silent 为 true 时入站不发送任何应答
+#[derive(Debug)]
+pub struct RejectError {
+    pub outbound: String,
+    pub silent: bool,
+}
+
+impl Display for RejectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "连接被出站 {} 拒绝", self.outbound)
+    }
+}
+
+impl std::error::Error for RejectError {}
@@ -0,0 +1,629 @@
+use std::{net::IpAddr, time::Duration};
+
+use bytes::BytesMut;
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, quic},
+    hkdf::{HKDF_SHA256, KeyType, Prk, Salt},
+};
+use tokio::{io::AsyncReadExt, net::TcpStream, time::timeout};
+use tracing::debug;
+
+use super::http::model::RequestHead;
+
+// 等待客户端首个数据包的最长时间，超时后按原目标路由
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(300);
+
+// 嗅探时最多读取的数据长度
+const MAX_SNIFF_SIZE: usize = 16 * 1024;
+
+// 嗅探结果
+pub enum Sniff {
+    // 嗅探到的域名
+    Domain(String),
+    // 数据不完整，需要继续读取
+    Incomplete,
+    // 不是可以识别的协议
+    Unknown,
+}
+
+// 读取客户端的首个数据包并嗅探其中的域名 (TLS SNI 或 HTTP Host)。
+// 读取到的数据保留在 buffer 中，需要由调用方先发送给出站
+pub async fn sniff_stream(stream: &mut TcpStream, buffer: &mut BytesMut) -> Option<String> {
+    let result = timeout(SNIFF_TIMEOUT, async {
+        loop {
+            if !buffer.is_empty() {
+                match sniff(buffer) {
+                    Sniff::Domain(domain) => return Some(domain),
+                    Sniff::Unknown => return None,
+                    Sniff::Incomplete if buffer.len() >= MAX_SNIFF_SIZE => return None,
+                    Sniff::Incomplete => {}
+                }
+            }
+            match stream.read_buf(buffer).await {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+    })
+    .await;
+    let domain = result.ok().flatten();
+    debug!("嗅探到的域名: {:?}", domain);
+    domain
+}
+
+pub fn sniff(data: &[u8]) -> Sniff {
+    match data.first() {
+        Some(0x16) => sniff_tls(data),
+        Some(byte) if byte.is_ascii_uppercase() => sniff_http(data),
+        _ => Sniff::Unknown,
+    }
+}
+
+// TLS 记录: 类型(1) 版本(2) 长度(2) 握手消息
+fn sniff_tls(data: &[u8]) -> Sniff {
+    if data.len() < 5 {
+        return Sniff::Incomplete;
+    }
+    let length = u16::from_be_bytes([data[3], data[4]]) as usize;
+    match data.get(5..5 + length) {
+        Some(handshake) => match parse_client_hello(handshake) {
+            Some(domain) => Sniff::Domain(domain),
+            None => Sniff::Unknown,
+        },
+        None => Sniff::Incomplete,
+    }
+}
+
+// 从 ClientHello 握手消息中取出 server_name 扩展中的主机名:
+// 类型(1) 长度(3) 版本(2) 随机数(32) 会话 ID(1+n) 密码套件(2+n) 压缩方法(1+n) 扩展(2+n)
+pub fn parse_client_hello(handshake: &[u8]) -> Option<String> {
+    let mut reader = Reader(handshake);
+    if reader.u8()? != 0x01 {
+        return None;
+    }
+    let length = reader.u24()?;
+    let mut reader = Reader(reader.bytes(length)?);
+    reader.bytes(2 + 32)?;
+    let session_id = reader.u8()? as usize;
+    reader.bytes(session_id)?;
+    let cipher_suites = reader.u16()? as usize;
+    reader.bytes(cipher_suites)?;
+    let compression = reader.u8()? as usize;
+    reader.bytes(compression)?;
+    let extensions = reader.u16()? as usize;
+    let mut extensions = Reader(reader.bytes(extensions)?);
+    while !extensions.0.is_empty() {
+        let r#type = extensions.u16()?;
+        let length = extensions.u16()? as usize;
+        let data = extensions.bytes(length)?;
+        // server_name: 列表长度(2) [类型(1) 长度(2) 主机名]
+        if r#type == 0x0000 {
+            let mut data = Reader(data);
+            let list = data.u16()? as usize;
+            let mut list = Reader(data.bytes(list)?);
+            while !list.0.is_empty() {
+                let name_type = list.u8()?;
+                let length = list.u16()? as usize;
+                let name = list.bytes(length)?;
+                if name_type == 0x00 {
+                    return valid_domain(std::str::from_utf8(name).ok()?);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn sniff_http(data: &[u8]) -> Sniff {
+    let Some(pos) = data.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Sniff::Incomplete;
+    };
+    let Ok(request_head) = RequestHead::decode(&data[..pos + 4].to_vec().into()) else {
+        return Sniff::Unknown;
+    };
+    let Some(host) = request_head.headers.get("host") else {
+        return Sniff::Unknown;
+    };
+    // 去掉端口，IPv6 字面量不是域名，无需处理
+    let domain = host
+        .rsplit_once(':')
+        .map_or(host.as_str(), |(host, _)| host);
+    match valid_domain(domain) {
+        Some(domain) => Sniff::Domain(domain),
+        None => Sniff::Unknown,
+    }
+}
+
+// 只接受域名，IP 地址没有嗅探的意义
+fn valid_domain(domain: &str) -> Option<String> {
+    let domain = domain.trim().trim_end_matches('.');
+    if domain.is_empty() || domain.parse::<IpAddr>().is_ok() || domain.contains(['[', ']']) {
+        return None;
+    }
+    Some(domain.to_ascii_lowercase())
+}
+
+// ============================================================================
+// QUIC Initial 包 (RFC 9000, RFC 9001)
+// 客户端 Initial 包使用由目标连接 ID 派生的密钥加密，解密后从 CRYPTO 帧中重组 ClientHello
+// ============================================================================
+
+const QUIC_VERSION_1: u32 = 0x0000_0001;
+
+// QUIC v1 的 Initial 盐值 (RFC 9001 5.2)
+const QUIC_V1_INITIAL_SALT: [u8; 20] = [
+    0x38, 0x76, 0x2c, 0xf7, 0xf5, 0x59, 0x34, 0xb3, 0x4d, 0x17, 0x9a, 0xe6, 0xa4, 0xc8, 0x0c, 0xad,
+    0xcc, 0xbb, 0x7f, 0x0a,
+];
+
+// ClientHello 可能分布在多个 Initial 包中，超过这个数量仍不完整时放弃
+const MAX_QUIC_PACKET: usize = 8;
+
+// 一个 QUIC 连接的嗅探状态，逐个传入客户端发送的数据报
+#[derive(Default)]
+pub struct QuicSniffer {
+    // CRYPTO 帧的 (偏移量, 数据)
+    crypto_vec: Vec<(usize, Vec<u8>)>,
+    packet_count: usize,
+}
+
+impl QuicSniffer {
+    pub fn push(&mut self, datagram: &[u8]) -> Sniff {
+        // 一个数据报中可能合并了多个长包头的包，短包头的包占满数据报的剩余部分
+        let mut rest = datagram;
+        let mut found = false;
+        while rest.first().is_some_and(|first| first & 0x80 != 0) {
+            let Some((length, plaintext)) = open_long_packet(rest) else {
+                break;
+            };
+            if let Some(plaintext) = plaintext {
+                if self.read_frames(&plaintext).is_none() {
+                    return Sniff::Unknown;
+                }
+                found = true;
+            }
+            rest = &rest[length..];
+        }
+        if !found {
+            return Sniff::Unknown;
+        }
+        self.packet_count += 1;
+        self.client_hello()
+    }
+
+    // 收集 CRYPTO 帧，遇到无法解析的帧时返回 None
+    fn read_frames(&mut self, plaintext: &[u8]) -> Option<()> {
+        let mut reader = Reader(plaintext);
+        while !reader.0.is_empty() {
+            match reader.varint()? {
+                // PADDING, PING
+                0x00 | 0x01 => {}
+                // ACK: 最大包号 延迟 区间数 首个区间 [间隔 区间]... [ECN 计数 x3]
+                frame_type @ (0x02 | 0x03) => {
+                    reader.varint()?;
+                    reader.varint()?;
+                    let range_count = reader.varint()?;
+                    reader.varint()?;
+                    for _ in 0..range_count {
+                        reader.varint()?;
+                        reader.varint()?;
+                    }
+                    if frame_type == 0x03 {
+                        for _ in 0..3 {
+                            reader.varint()?;
+                        }
+                    }
+                }
+                // CRYPTO: 偏移量 长度 数据
+                0x06 => {
+                    let offset = reader.varint()? as usize;
+                    let length = reader.varint()? as usize;
+                    let data = reader.bytes(length)?;
+                    if offset.checked_add(length)? > MAX_SNIFF_SIZE {
+                        return None;
+                    }
+                    self.crypto_vec.push((offset, data.to_vec()));
+                }
+                // CONNECTION_CLOSE
+                0x1c => return Some(()),
+                _ => return None,
+            }
+        }
+        Some(())
+    }
+
+    // 按偏移量拼接 CRYPTO 数据，得到完整的 ClientHello 后取出 SNI
+    fn client_hello(&mut self) -> Sniff {
+        self.crypto_vec.sort_by_key(|(offset, _)| *offset);
+        let mut handshake = Vec::new();
+        for (offset, data) in &self.crypto_vec {
+            if *offset > handshake.len() {
+                break;
+            }
+            let skip = handshake.len() - offset;
+            if data.len() > skip {
+                handshake.extend_from_slice(&data[skip..]);
+            }
+        }
+
+        let length = match handshake.get(1..4) {
+            Some(length) => 4 + u32::from_be_bytes([0, length[0], length[1], length[2]]) as usize,
+            None => usize::MAX,
+        };
+        match handshake.get(..length) {
+            Some(client_hello) => match parse_client_hello(client_hello) {
+                Some(domain) => Sniff::Domain(domain),
+                None => Sniff::Unknown,
+            },
+            None if self.packet_count < MAX_QUIC_PACKET => Sniff::Incomplete,
+            None => Sniff::Unknown,
+        }
+    }
+}
+
+// 解析长包头: 首字节(1) 版本(4) 目标连接 ID(1+n) 源连接 ID(1+n) [令牌(varint+n)] 长度(varint) 包号 负载。
+// 返回包的长度，以及 Initial 包解密后的负载
+fn open_long_packet(packet: &[u8]) -> Option<(usize, Option<Vec<u8>>)> {
+    let mut reader = Reader(packet);
+    let first = reader.u8()?;
+    if reader.u32()? != QUIC_VERSION_1 {
+        return None;
+    }
+    let dcid_length = reader.u8()? as usize;
+    let dcid = reader.bytes(dcid_length)?;
+    let scid_length = reader.u8()? as usize;
+    reader.bytes(scid_length)?;
+    let packet_type = (first >> 4) & 0x03;
+    match packet_type {
+        // Initial 包带有令牌
+        0x00 => {
+            let token_length = reader.varint()? as usize;
+            reader.bytes(token_length)?;
+        }
+        // Retry 包只由服务端发送
+        0x03 => return None,
+        _ => {}
+    }
+    let length = reader.varint()? as usize;
+    let pn_offset = packet.len() - reader.0.len();
+    let packet_length = pn_offset.checked_add(length)?;
+    let packet = packet.get(..packet_length)?;
+    if packet_type != 0x00 {
+        return Some((packet_length, None));
+    }
+    Some((packet_length, decrypt_initial(packet, pn_offset, dcid)))
+}
+
+// 由目标连接 ID 派生客户端 Initial 包的 (密钥, IV, 包头保护密钥) (RFC 9001 5.2)
+fn initial_keys(dcid: &[u8]) -> Option<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    let initial_secret = Salt::new(HKDF_SHA256, &QUIC_V1_INITIAL_SALT).extract(dcid);
+    let client_secret = expand_label(&initial_secret, b"client in", 32)?;
+    let client_secret = Prk::new_less_safe(HKDF_SHA256, &client_secret);
+    Some((
+        expand_label(&client_secret, b"quic key", 16)?,
+        expand_label(&client_secret, b"quic iv", 12)?,
+        expand_label(&client_secret, b"quic hp", 16)?,
+    ))
+}
+
+// 解密客户端 Initial 包 (RFC 9001 5)
+fn decrypt_initial(packet: &[u8], pn_offset: usize, dcid: &[u8]) -> Option<Vec<u8>> {
+    let (key, iv, hp) = initial_keys(dcid)?;
+
+    // 去除包头保护: 掩码由包号起始 4 字节之后的 16 字节采样生成
+    let sample = packet.get(pn_offset + 4..pn_offset + 20)?;
+    let mask = quic::HeaderProtectionKey::new(&quic::AES_128, &hp)
+        .ok()?
+        .new_mask(sample)
+        .ok()?;
+    let mut header = packet[..pn_offset + 4].to_vec();
+    header[0] ^= mask[0] & 0x0f;
+    let pn_length = (header[0] & 0x03) as usize + 1;
+    header.truncate(pn_offset + pn_length);
+    let mut packet_number = 0u64;
+    for i in 0..pn_length {
+        header[pn_offset + i] ^= mask[1 + i];
+        packet_number = packet_number << 8 | header[pn_offset + i] as u64;
+    }
+
+    // 随机数为 IV 与包号异或，客户端的首批 Initial 包号很小，截断的包号即为完整包号
+    let mut nonce = [0u8; 12];
+    nonce.copy_from_slice(&iv);
+    for (byte, pn) in nonce[4..].iter_mut().zip(packet_number.to_be_bytes()) {
+        *byte ^= pn;
+    }
+    let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).ok()?);
+    let mut payload = packet[pn_offset + pn_length..].to_vec();
+    let plaintext = key
+        .open_in_place(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&header),
+            &mut payload,
+        )
+        .ok()?;
+    Some(plaintext.to_vec())
+}
+
+// HKDF-Expand-Label (RFC 8446 7.1): 长度(2) 标签长度(1) "tls13 " 标签 上下文长度(1)
+fn expand_label(prk: &Prk, label: &[u8], length: usize) -> Option<Vec<u8>> {
+    struct Length(usize);
+    impl KeyType for Length {
+        fn len(&self) -> usize {
+            self.0
+        }
+    }
+
+    let output_length = (length as u16).to_be_bytes();
+    let label_length = [(b"tls13 ".len() + label.len()) as u8];
+    let info: [&[u8]; 5] = [&output_length, &label_length, b"tls13 ", label, &[0]];
+    let mut output = vec![0u8; length];
+    prk.expand(&info, Length(length))
+        .ok()?
+        .fill(&mut output)
+        .ok()?;
+    Some(output)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        if self.0.len() < length {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let bytes = self.bytes(3)?;
+        Some(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let bytes = self.bytes(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    // QUIC 变长整数: 首字节的高 2 位表示长度为 1, 2, 4 或 8 字节
+    fn varint(&mut self) -> Option<u64> {
+        let first = *self.0.first()?;
+        let bytes = self.bytes(1 << (first >> 6))?;
+        Some(
+            bytes[1..]
+                .iter()
+                .fold((first & 0x3f) as u64, |value, byte| {
+                    value << 8 | *byte as u64
+                }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn domain(sniff: Sniff) -> Option<String> {
+        match sniff {
+            Sniff::Domain(domain) => Some(domain),
+            _ => None,
+        }
+    }
+
+    fn with_length(length_size: usize, data: &[u8]) -> Vec<u8> {
+        let mut buffer = data.len().to_be_bytes()[8 - length_size..].to_vec();
+        buffer.extend_from_slice(data);
+        buffer
+    }
+
+    fn extension(r#type: u16, data: &[u8]) -> Vec<u8> {
+        let mut buffer = r#type.to_be_bytes().to_vec();
+        buffer.extend(with_length(2, data));
+        buffer
+    }
+
+    // 构造包含 ClientHello 的 TLS 记录，返回记录和 server_name 扩展在其中的偏移量
+    fn client_hello(sni: Option<&str>) -> (Vec<u8>, usize) {
+        let mut body = vec![0x03, 0x03];
+        body.extend([0u8; 32]);
+        body.extend(with_length(1, &[0u8; 32]));
+        body.extend(with_length(2, &[0x13, 0x01, 0x13, 0x02]));
+        body.extend(with_length(1, &[0x00]));
+        // supported_versions: TLS 1.3
+        let mut extensions = extension(0x002b, &[0x02, 0x03, 0x04]);
+        let sni_offset = extensions.len();
+        if let Some(sni) = sni {
+            let mut name = vec![0x00];
+            name.extend(with_length(2, sni.as_bytes()));
+            extensions.extend(extension(0x0000, &with_length(2, &name)));
+        }
+        body.extend(with_length(2, &extensions));
+        let sni_offset = body.len() - extensions.len() + sni_offset;
+
+        let mut handshake = vec![0x01];
+        handshake.extend(with_length(3, &body));
+        let mut record = vec![0x16, 0x03, 0x01];
+        record.extend(with_length(2, &handshake));
+        (record, 5 + 4 + sni_offset)
+    }
+
+    #[test]
+    fn tls_sni() {
+        let (record, _) = client_hello(Some("WWW.Example.com."));
+        assert_eq!(domain(sniff(&record)).as_deref(), Some("www.example.com"));
+        assert_eq!(
+            parse_client_hello(&record[5..]).as_deref(),
+            Some("www.example.com")
+        );
+    }
+
+    #[test]
+    fn tls_without_sni() {
+        let (record, _) = client_hello(None);
+        assert!(matches!(sniff(&record), Sniff::Unknown));
+        assert_eq!(parse_client_hello(&record[5..]), None);
+    }
+
+    #[test]
+    fn tls_truncated() {
+        let (record, sni_offset) = client_hello(Some("example.com"));
+        // 记录头不完整
+        assert!(matches!(sniff(&record[..3]), Sniff::Incomplete));
+        // 记录在 server_name 扩展中间截断
+        assert_eq!(record[sni_offset..sni_offset + 2], [0x00, 0x00]);
+        assert!(matches!(
+            sniff(&record[..sni_offset + 6]),
+            Sniff::Incomplete
+        ));
+        assert_eq!(parse_client_hello(&record[5..sni_offset + 6]), None);
+    }
+
+    #[test]
+    fn http_host() {
+        let request = b"GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n";
+        assert_eq!(domain(sniff(request)).as_deref(), Some("example.com"));
+        let request = b"GET / HTTP/1.1\r\nHost: [::1]:80\r\n\r\n";
+        assert!(matches!(sniff(request), Sniff::Unknown));
+        let request = b"GET / HTTP/1.1\r\nHost: example.com\r\n";
+        assert!(matches!(sniff(request), Sniff::Incomplete));
+    }
+
+    // RFC 9001 A.2 中客户端发送的 Initial 包，ClientHello 的 SNI 为 example.com
+    const RFC9001_CLIENT_INITIAL: &str = "\
+        c000000001088394c8f03e5157080000449e7b9aec34d1b1c98dd7689fb8ec11\
+        d242b123dc9bd8bab936b47d92ec356c0bab7df5976d27cd449f63300099f399\
+        1c260ec4c60d17b31f8429157bb35a1282a643a8d2262cad67500cadb8e7378c\
+        8eb7539ec4d4905fed1bee1fc8aafba17c750e2c7ace01e6005f80fcb7df6212\
+        30c83711b39343fa028cea7f7fb5ff89eac2308249a02252155e2347b63d58c5\
+        457afd84d05dfffdb20392844ae812154682e9cf012f9021a6f0be17ddd0c208\
+        4dce25ff9b06cde535d0f920a2db1bf362c23e596d11a4f5a6cf3948838a3aec\
+        4e15daf8500a6ef69ec4e3feb6b1d98e610ac8b7ec3faf6ad760b7bad1db4ba3\
+        485e8a94dc250ae3fdb41ed15fb6a8e5eba0fc3dd60bc8e30c5c4287e53805db\
+        059ae0648db2f64264ed5e39be2e20d82df566da8dd5998ccabdae053060ae6c\
+        7b4378e846d29f37ed7b4ea9ec5d82e7961b7f25a9323851f681d582363aa5f8\
+        9937f5a67258bf63ad6f1a0b1d96dbd4faddfcefc5266ba6611722395c906556\
+        be52afe3f565636ad1b17d508b73d8743eeb524be22b3dcbc2c7468d54119c74\
+        68449a13d8e3b95811a198f3491de3e7fe942b330407abf82a4ed7c1b311663a\
+        c69890f4157015853d91e923037c227a33cdd5ec281ca3f79c44546b9d90ca00\
+        f064c99e3dd97911d39fe9c5d0b23a229a234cb36186c4819e8b9c5927726632\
+        291d6a418211cc2962e20fe47feb3edf330f2c603a9d48c0fcb5699dbfe58964\
+        25c5bac4aee82e57a85aaf4e2513e4f05796b07ba2ee47d80506f8d2c25e50fd\
+        14de71e6c418559302f939b0e1abd576f279c4b2e0feb85c1f28ff18f58891ff\
+        ef132eef2fa09346aee33c28eb130ff28f5b766953334113211996d20011a198\
+        e3fc433f9f2541010ae17c1bf202580f6047472fb36857fe843b19f5984009dd\
+        c324044e847a4f4a0ab34f719595de37252d6235365e9b84392b061085349d73\
+        203a4a13e96f5432ec0fd4a1ee65accdd5e3904df54c1da510b0ff20dcc0c77f\
+        cb2c0e0eb605cb0504db87632cf3d8b4dae6e705769d1de354270123cb11450e\
+        fc60ac47683d7b8d0f811365565fd98c4c8eb936bcab8d069fc33bd801b03ade\
+        a2e1fbc5aa463d08ca19896d2bf59a071b851e6c239052172f296bfb5e724047\
+        90a2181014f3b94a4e97d117b438130368cc39dbb2d198065ae3986547926cd2\
+        162f40a29f0c3c8745c0f50fba3852e566d44575c29d39a03f0cda721984b6f4\
+        40591f355e12d439ff150aab7613499dbd49adabc8676eef023b15b65bfc5ca0\
+        6948109f23f350db82123535eb8a7433bdabcb909271a6ecbcb58b936a88cd4e\
+        8f2e6ff5800175f113253d8fa9ca8885c2f552e657dc603f252e1a8e308f76f0\
+        be79e2fb8f5d5fbbe2e30ecadd220723c8c0aea8078cdfcb3868263ff8f09400\
+        54da48781893a7e49ad5aff4af300cd804a6b6279ab3ff3afb64491c85194aab\
+        760d58a606654f9f4400e8b38591356fbf6425aca26dc85244259ff2b19c41b9\
+        f96f3ca9ec1dde434da7d2d392b905ddf3d1f9af93d1af5950bd493f5aa731b4\
+        056df31bd267b6b90a079831aaf579be0a39013137aac6d404f518cfd4684064\
+        7e78bfe706ca4cf5e9c5453e9f7cfd2b8b4c8d169a44e55c88d4a9a7f9474241\
+        e221af44860018ab0856972e194cd934\
+    ";
+
+    fn hex(text: &str) -> Vec<u8> {
+        let text = text.split_whitespace().collect::<String>();
+        (0..text.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // 按 RFC 9001 5 加密并保护一个客户端 Initial 包，负载填充到 1200 字节的数据报
+    fn seal_initial(dcid: &[u8], packet_number: u8, frames: &[u8]) -> Vec<u8> {
+        let (key, iv, hp) = initial_keys(dcid).unwrap();
+        // 首字节(1) 版本(4) 目标连接 ID(1+n) 源连接 ID(1) 令牌(1) 长度(2) 包号(1)
+        let header_length = 1 + 4 + 1 + dcid.len() + 1 + 1 + 2 + 1;
+        let mut payload = frames.to_vec();
+        payload.resize(1200 - header_length - 16, 0x00);
+        let mut packet = vec![0xc0];
+        packet.extend(QUIC_VERSION_1.to_be_bytes());
+        packet.extend(with_length(1, dcid));
+        packet.extend([0x00, 0x00]);
+        packet.extend((0x4000 | (1 + payload.len() + 16) as u16).to_be_bytes());
+        packet.push(packet_number);
+
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&iv);
+        nonce[11] ^= packet_number;
+        let key = LessSafeKey::new(UnboundKey::new(&aead::AES_128_GCM, &key).unwrap());
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&packet),
+            &mut payload,
+        )
+        .unwrap();
+        let pn_offset = packet.len() - 1;
+        packet.extend(payload);
+
+        let mask = quic::HeaderProtectionKey::new(&quic::AES_128, &hp)
+            .unwrap()
+            .new_mask(&packet[pn_offset + 4..pn_offset + 20])
+            .unwrap();
+        packet[0] ^= mask[0] & 0x0f;
+        packet[pn_offset] ^= mask[1];
+        packet
+    }
+
+    fn crypto_frame(offset: usize, data: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0x06];
+        frame.extend((0x4000 | offset as u16).to_be_bytes());
+        frame.extend((0x4000 | data.len() as u16).to_be_bytes());
+        frame.extend_from_slice(data);
+        frame
+    }
+
+    #[test]
+    fn quic_rfc9001_client_initial() {
+        let packet = hex(RFC9001_CLIENT_INITIAL);
+        assert_eq!(packet.len(), 1200);
+        let mut sniffer = QuicSniffer::default();
+        assert_eq!(
+            domain(sniffer.push(&packet)).as_deref(),
+            Some("example.com")
+        );
+    }
+
+    #[test]
+    fn quic_split_client_hello_out_of_order() {
+        // 取出 RFC 9001 A.2 中的 ClientHello: CRYPTO 帧 06 偏移量 00 长度 40f1
+        let packet = hex(RFC9001_CLIENT_INITIAL);
+        let dcid = &packet[6..14];
+        let (_, plaintext) = open_long_packet(&packet).unwrap();
+        let plaintext = plaintext.unwrap();
+        assert_eq!(plaintext[..4], [0x06, 0x00, 0x40, 0xf1]);
+        let client_hello = &plaintext[4..4 + 0xf1];
+
+        let (first, second) = client_hello.split_at(client_hello.len() / 2);
+        let mut frames = vec![0x01];
+        frames.extend(crypto_frame(first.len(), second));
+        let packet_1 = seal_initial(dcid, 1, &frames);
+        let packet_0 = seal_initial(dcid, 0, &crypto_frame(0, first));
+
+        let mut sniffer = QuicSniffer::default();
+        assert!(matches!(sniffer.push(&packet_1), Sniff::Incomplete));
+        assert_eq!(
+            domain(sniffer.push(&packet_0)).as_deref(),
+            Some("example.com")
+        );
+    }
+}
@@ -1,10 +1,44 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::Result;
-use tokio::net::{TcpListener, TcpStream};
-use tracing::{info, trace, warn};
+use anyhow::{Result, bail, ensure};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, copy_bidirectional},
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinHandle,
+    time::timeout,
+};
+use tracing::{debug, info, trace, warn};
 
-use super::Socks5;
+use super::{
+    Socks5,
+    model::{
+        ATYP_DOMAIN, ATYP_IPV4, ATYP_IPV6, AUTH_FAILURE, AUTH_SUCCESS, AUTH_VERSION,
+        COMMAND_CONNECT, COMMAND_UDP_ASSOCIATE, METHOD_NO_ACCEPTABLE, METHOD_NO_AUTH,
+        METHOD_USERNAME_PASSWORD, Reply, VERSION, decode_udp_packet, encode_udp_packet,
+        read_address,
+    },
+};
+use crate::{
+    config::outbound::Outbound,
+    protocol::{
+        address::Address,
+        reject::RejectError,
+        sniff::{QuicSniffer, Sniff, sniff_stream},
+        udp::{Datagram, UDP_BUFFER_SIZE},
+    },
+    service::{
+        ServiceConfig,
+        process::{Network, ProcessInfo},
+        route::RouteContext,
+    },
+};
 
 impl Socks5 {
     pub async fn listen(&self) -> Result<()> {
@@ -30,10 +64,377 @@ impl Socks5 {
         }
     }
 
-    pub async fn accept(&self, stream: TcpStream, addr: SocketAddr) -> Result<()> {
-        // 处理连接
-        // 传递连接
+    pub async fn accept(&self, mut stream: TcpStream, addr: SocketAddr) -> Result<()> {
+        self.negotiate(&mut stream).await?;
 
+        // 请求: VER CMD RSV ATYP DST.ADDR DST.PORT
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let [version, command, _, atyp] = header;
+        ensure!(version == VERSION, "不支持的 SOCKS 版本: {}", version);
+
+        if !matches!(atyp, ATYP_IPV4 | ATYP_DOMAIN | ATYP_IPV6) {
+            reply(&mut stream, Reply::AddressTypeNotSupported).await?;
+            bail!("不支持的地址类型: {}", atyp);
+        }
+        let target = read_address(&mut stream, atyp).await?;
+        debug!("来自 {} 的请求: 命令 {} 目标 {}", addr, command, target);
+
+        match command {
+            COMMAND_CONNECT => self.handle_connect(stream, addr, target).await,
+            COMMAND_UDP_ASSOCIATE => self.handle_udp_associate(stream, addr).await,
+            _ => {
+                reply(&mut stream, Reply::CommandNotSupported).await?;
+                bail!("不支持的命令: {}", command)
+            }
+        }
+    }
+
+    // 方法协商: VER NMETHODS METHODS
+    async fn negotiate(&self, stream: &mut TcpStream) -> Result<()> {
+        let version = stream.read_u8().await?;
+        ensure!(version == VERSION, "不支持的 SOCKS 版本: {}", version);
+
+        let nmethods = stream.read_u8().await?;
+        let mut methods = vec![0u8; nmethods as usize];
+        stream.read_exact(&mut methods).await?;
+
+        let method = if self.auth_enable {
+            METHOD_USERNAME_PASSWORD
+        } else {
+            METHOD_NO_AUTH
+        };
+        if !methods.contains(&method) {
+            stream.write_all(&[VERSION, METHOD_NO_ACCEPTABLE]).await?;
+            bail!("客户端没有可接受的认证方法: {:?}", methods);
+        }
+        stream.write_all(&[VERSION, method]).await?;
+
+        if self.auth_enable {
+            self.verify_auth(stream).await?;
+        }
+        Ok(())
+    }
+
+    // 用户名/密码子协商: VER ULEN UNAME PLEN PASSWD
+    async fn verify_auth(&self, stream: &mut TcpStream) -> Result<()> {
+        let version = stream.read_u8().await?;
+        ensure!(version == AUTH_VERSION, "不支持的认证协议版本: {}", version);
+
+        let ulen = stream.read_u8().await?;
+        let mut username = vec![0u8; ulen as usize];
+        stream.read_exact(&mut username).await?;
+        let plen = stream.read_u8().await?;
+        let mut password = vec![0u8; plen as usize];
+        stream.read_exact(&mut password).await?;
+
+        let accepted = self.user.iter().any(|user| {
+            user.username.as_bytes() == username && user.password.as_bytes() == password
+        });
+        if !accepted {
+            stream.write_all(&[AUTH_VERSION, AUTH_FAILURE]).await?;
+            bail!("用户 {} 认证失败", String::from_utf8_lossy(&username));
+        }
+        stream.write_all(&[AUTH_VERSION, AUTH_SUCCESS]).await?;
         Ok(())
     }
+
+    // 处理 CONNECT 命令: 连接出站后回复成功，然后双向转发数据。
+    // 嗅探时需要先回复成功，客户端才会发送首个数据包
+    async fn handle_connect(
+        &self,
+        mut stream: TcpStream,
+        addr: SocketAddr,
+        target: Address,
+    ) -> Result<()> {
+        let service = ServiceConfig::get()?;
+        let sniff = self.sniff && matches!(target, Address::Ip(_));
+        let mut context = RouteContext::new(target)
+            .with_source(addr)
+            .with_inbound(&self.name);
+        let mut buffer = BytesMut::new();
+        if sniff {
+            reply(&mut stream, Reply::Succeeded).await?;
+            let domain = sniff_stream(&mut stream, &mut buffer).await;
+            context = context.with_sniffed(domain, self.sniff_override_destination);
+        }
+        let mut outbound_stream = match service.connect(context).await {
+            Ok(outbound_stream) => outbound_stream,
+            // 已经回复成功时只能直接关闭连接
+            Err(e) => {
+                if let Some(reject) = e.downcast_ref::<RejectError>() {
+                    if !reject.silent && !sniff {
+                        reply(&mut stream, Reply::NotAllowed).await?;
+                    }
+                    debug!("{}", reject);
+                    return Ok(());
+                }
+                if !sniff {
+                    reply(&mut stream, Reply::from_error(&e)).await?;
+                }
+                return Err(e);
+            }
+        };
+
+        if !sniff {
+            reply(&mut stream, Reply::Succeeded).await?;
+        }
+        // 嗅探时读取到的数据
+        if !buffer.is_empty() {
+            outbound_stream.write_all(&buffer).await?;
+        }
+        copy_bidirectional(&mut stream, &mut outbound_stream).await?;
+        Ok(())
+    }
+
+    // 处理 UDP ASSOCIATE 命令: 绑定 UDP 中继套接字，直到控制连接关闭
+    async fn handle_udp_associate(&self, mut stream: TcpStream, addr: SocketAddr) -> Result<()> {
+        // 中继套接字绑定在与控制连接相同的本地地址上，保证客户端可达
+        let local = SocketAddr::new(stream.local_addr()?.ip(), 0);
+        let socket = match UdpSocket::bind(local).await {
+            Ok(socket) => Arc::new(socket),
+            Err(e) => {
+                reply(&mut stream, Reply::GeneralFailure).await?;
+                return Err(e.into());
+            }
+        };
+        let bind = Address::Ip(socket.local_addr()?);
+        stream.write_all(&Reply::Succeeded.encode(&bind)?).await?;
+        debug!("{} 的 UDP 中继绑定在 {}", addr, bind);
+
+        let mut relay = UdpRelay::new(socket, addr.ip(), &self.name, self.sniff);
+        let mut buffer = [0u8; 1];
+        let result = tokio::select! {
+            // 控制连接关闭 (或客户端违规发送数据) 时结束关联
+            _ = stream.read(&mut buffer) => Ok(()),
+            result = relay.run() => result,
+        };
+        relay.close();
+        debug!("{} 的 UDP 关联结束", addr);
+        result
+    }
+}
+
+// UDP 关联在没有任何客户端数据报时的最长存活时间
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+// 一个 UDP 关联的中继状态，每个出站复用一个 UDP 通道
+struct UdpRelay {
+    socket: Arc<UdpSocket>,
+    client_ip: IpAddr,
+    // 接收 UDP 关联的入站名
+    inbound: String,
+    // 是否嗅探 QUIC Initial 包中的 SNI
+    sniff: bool,
+    // 每个 IP 目标嗅探到的域名，之后发往该目标的数据报按同一域名路由
+    sniff_hash_map: HashMap<SocketAddr, String>,
+    // 正在嗅探的 IP 目标，ClientHello 完整前暂存客户端的数据报
+    quic_hash_map: HashMap<SocketAddr, (QuicSniffer, Vec<Bytes>)>,
+    // 客户端所属的进程，第一次有规则需要时查找
+    process: Option<Option<ProcessInfo>>,
+    // 每个目标 (嗅探到域名时为域名) 路由到的出站
+    route_hash_map: HashMap<Address, Outbound>,
+    // 每个出站的 UDP 通道及其回程转发任务，任务结束说明通道已失效，下次使用时重建
+    session_hash_map: HashMap<String, (Arc<Datagram>, JoinHandle<()>)>,
+}
+
+impl UdpRelay {
+    fn new(socket: Arc<UdpSocket>, client_ip: IpAddr, inbound: &str, sniff: bool) -> Self {
+        Self {
+            socket,
+            client_ip,
+            inbound: inbound.to_string(),
+            sniff,
+            sniff_hash_map: HashMap::new(),
+            quic_hash_map: HashMap::new(),
+            process: None,
+            route_hash_map: HashMap::new(),
+            session_hash_map: HashMap::new(),
+        }
+    }
+
+    async fn run(&mut self) -> Result<()> {
+        loop {
+            let mut buffer = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+            let client =
+                match timeout(UDP_IDLE_TIMEOUT, self.socket.recv_buf_from(&mut buffer)).await {
+                    Ok(result) => result?.1,
+                    Err(_) => {
+                        debug!("UDP 关联空闲超时");
+                        return Ok(());
+                    }
+                };
+
+            // 只接受来自控制连接客户端的数据报
+            if client.ip() != self.client_ip {
+                trace!("丢弃来自 {} 的 UDP 数据报", client);
+                continue;
+            }
+
+            let (target, payload) = match decode_udp_packet(buffer.freeze()) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    debug!("丢弃来自 {} 的 UDP 数据报: {}", client, e);
+                    continue;
+                }
+            };
+
+            // 嗅探完成前数据报先暂存，完成后一起发送
+            let Some(payload_vec) = self.sniff_quic(&target, payload) else {
+                continue;
+            };
+
+            let datagram = match self.session(&target, client).await {
+                Ok(datagram) => datagram,
+                Err(e) => {
+                    debug!("UDP 数据报 {} -> {} 无法转发: {}", client, target, e);
+                    continue;
+                }
+            };
+            for payload in payload_vec {
+                if let Err(e) = datagram.send_to(&payload, &target).await {
+                    debug!("UDP 数据报 {} -> {} 发送失败: {}", client, target, e);
+                }
+            }
+        }
+    }
+
+    // 嗅探发往 IP 目标的 QUIC Initial 包，返回可以发送的数据报，ClientHello 不完整时返回 None
+    fn sniff_quic(&mut self, target: &Address, payload: Bytes) -> Option<Vec<Bytes>> {
+        let Address::Ip(addr) = target else {
+            return Some(vec![payload]);
+        };
+        if !self.sniff || self.sniff_hash_map.contains_key(addr) {
+            return Some(vec![payload]);
+        }
+
+        let (sniffer, payload_vec) = self.quic_hash_map.entry(*addr).or_default();
+        let result = sniffer.push(&payload);
+        payload_vec.push(payload);
+        match result {
+            Sniff::Incomplete => return None,
+            Sniff::Domain(domain) => {
+                debug!("嗅探到 QUIC {} 的域名: {}", addr, domain);
+                self.sniff_hash_map.insert(*addr, domain);
+            }
+            Sniff::Unknown => {}
+        }
+        self.quic_hash_map
+            .remove(addr)
+            .map(|(_, payload_vec)| payload_vec)
+    }
+
+    // 获取目标地址路由到的出站通道，不存在时新建并启动回程转发
+    async fn session(&mut self, target: &Address, client: SocketAddr) -> Result<Arc<Datagram>> {
+        // 嗅探到的域名只用于路由，数据报仍发往客户端请求的 IP，保证回程的源地址不变
+        let domain = match target {
+            Address::Ip(addr) => self.sniff_hash_map.get(addr).cloned(),
+            Address::Domain(..) => None,
+        };
+        let mut context = RouteContext::new(target.clone())
+            .with_source(client)
+            .with_inbound(&self.inbound)
+            .with_sniffed(domain, false);
+        // 每个目标只路由一次，之后的数据报复用结果，避免逐个数据报解析域名，
+        // 规则命中次数也按流而不是按数据报统计
+        let outbound = match self.route_hash_map.get(&context.target) {
+            Some(outbound) => outbound.clone(),
+            None => {
+                let service = ServiceConfig::get()?;
+                // 关联的客户端不变，进程只查找一次
+                match &self.process {
+                    Some(process) => context = context.with_process(process.clone()),
+                    None => {
+                        let route_manager = service.route_manager();
+                        if route_manager.need_process(&context) {
+                            context
+                                .lookup_process(Network::Udp, route_manager.need_pid())
+                                .await;
+                            self.process = Some(context.process.clone());
+                        }
+                    }
+                }
+                let route_target = context.target.clone();
+                let outbound = service.route(context).await?;
+                info!("UDP {} 经由出站 {}", route_target, outbound.name());
+                self.route_hash_map.insert(route_target, outbound.clone());
+                outbound
+            }
+        };
+        match self.session_hash_map.get(outbound.name()) {
+            Some((datagram, task)) if !task.is_finished() => return Ok(datagram.clone()),
+            Some(_) => debug!("出站 {} 的 UDP 通道已失效，重新建立", outbound.name()),
+            None => {}
+        }
+
+        let datagram = Arc::new(outbound.bind_udp().await?);
+        let socket = self.socket.clone();
+        let receiver = datagram.clone();
+        // 单个数据报无法解码或发送失败时只丢弃该数据报，通道本身失效时才结束
+        let task = tokio::spawn(async move {
+            loop {
+                let (payload, source) = match receiver.recv_from().await {
+                    Ok(packet) => packet,
+                    Err(e) if is_socket_dead(&e) => {
+                        debug!("UDP 出站接收失败: {}", e);
+                        break;
+                    }
+                    Err(e) => {
+                        debug!("丢弃 UDP 出站的数据报: {}", e);
+                        continue;
+                    }
+                };
+                let packet = match encode_udp_packet(&source, &payload) {
+                    Ok(packet) => packet,
+                    Err(e) => {
+                        debug!("丢弃来自 {} 的 UDP 数据报: {}", source, e);
+                        continue;
+                    }
+                };
+                if let Err(e) = socket.send_to(&packet, client).await {
+                    debug!("UDP 数据报 {} -> {} 发送失败: {}", source, client, e);
+                }
+            }
+        });
+        if let Some((_, task)) = self
+            .session_hash_map
+            .insert(outbound.name().to_string(), (datagram.clone(), task))
+        {
+            task.abort();
+        }
+
+        Ok(datagram)
+    }
+
+    fn close(&mut self) {
+        for (_, (_, task)) in self.session_hash_map.drain() {
+            task.abort();
+        }
+        self.sniff_hash_map.clear();
+        self.quic_hash_map.clear();
+        self.route_hash_map.clear();
+    }
+}
+
+// 接收错误是否说明 UDP 通道已经不可用。解码错误只涉及单个数据报，
+// 已连接的套接字收到的 ICMP 错误 (如 ECONNREFUSED) 也只是暂时的
+fn is_socket_dead(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<io::Error>().is_some_and(|e| {
+        !matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::HostUnreachable
+                | ErrorKind::NetworkUnreachable
+                | ErrorKind::Interrupted
+                | ErrorKind::WouldBlock
+        )
+    })
+}
+
+// 发送应答，绑定地址统一使用 0.0.0.0:0
+async fn reply(stream: &mut TcpStream, reply: Reply) -> Result<()> {
+    let bind = Address::Ip(SocketAddr::from(([0, 0, 0, 0], 0)));
+    stream.write_all(&reply.encode(&bind)?).await?;
+    Ok(())
 }
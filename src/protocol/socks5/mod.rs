@@ -1,4 +1,5 @@
 pub mod inbound;
+pub mod model;
 pub mod outbound;
 
 use serde::Deserialize;
@@ -8,4 +9,26 @@ pub struct Socks5 {
     pub name: String,
     pub host: String,
     pub port: u16,
+    // 是否启用用户名/密码认证 (RFC 1929)
+    #[serde(default)]
+    pub auth_enable: bool,
+    // 入站: 允许连接的用户列表
+    #[serde(default)]
+    pub user: Vec<Socks5User>,
+    // 出站: 连接上游时使用的用户名和密码
+    pub username: Option<String>,
+    pub password: Option<String>,
+    // 入站: 嗅探 CONNECT 命令首个数据包中的 TLS SNI 或 HTTP Host，
+    // 以及 UDP 关联中 QUIC Initial 包的 SNI，用于按域名路由 IP 目标
+    #[serde(default)]
+    pub sniff: bool,
+    // 入站: CONNECT 命令连接嗅探到的域名而不是客户端请求的 IP，UDP 数据报不受影响
+    #[serde(default)]
+    pub sniff_override_destination: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Socks5User {
+    pub username: String,
+    pub password: String,
 }
@@ -0,0 +1,245 @@
+use std::{
+    fmt::Display,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+};
+
+use anyhow::{Result, bail, ensure};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::protocol::{address::Address, http::outbound::ConnectError};
+
+// 协议版本
+pub const VERSION: u8 = 0x05;
+
+// 认证方法 (RFC 1928 Section 3)
+pub const METHOD_NO_AUTH: u8 = 0x00;
+pub const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+pub const METHOD_NO_ACCEPTABLE: u8 = 0xFF;
+
+// 用户名/密码认证 (RFC 1929)
+pub const AUTH_VERSION: u8 = 0x01;
+pub const AUTH_SUCCESS: u8 = 0x00;
+pub const AUTH_FAILURE: u8 = 0x01;
+
+// 请求命令 (RFC 1928 Section 4)
+pub const COMMAND_CONNECT: u8 = 0x01;
+pub const COMMAND_UDP_ASSOCIATE: u8 = 0x03;
+
+// 地址类型 (RFC 1928 Section 5)
+pub const ATYP_IPV4: u8 = 0x01;
+pub const ATYP_DOMAIN: u8 = 0x03;
+pub const ATYP_IPV6: u8 = 0x04;
+
+// 应答码 (RFC 1928 Section 6)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Reply {
+    Succeeded = 0x00,
+    GeneralFailure = 0x01,
+    NotAllowed = 0x02,
+    NetworkUnreachable = 0x03,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    TtlExpired = 0x06,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
+
+impl Reply {
+    pub fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            0x00 => Some(Reply::Succeeded),
+            0x01 => Some(Reply::GeneralFailure),
+            0x02 => Some(Reply::NotAllowed),
+            0x03 => Some(Reply::NetworkUnreachable),
+            0x04 => Some(Reply::HostUnreachable),
+            0x05 => Some(Reply::ConnectionRefused),
+            0x06 => Some(Reply::TtlExpired),
+            0x07 => Some(Reply::CommandNotSupported),
+            0x08 => Some(Reply::AddressTypeNotSupported),
+            _ => None,
+        }
+    }
+
+    // 根据连接出站时的错误选择合适的应答码
+    pub fn from_error(error: &anyhow::Error) -> Self {
+        use std::io::ErrorKind;
+
+        // 上游 SOCKS5 服务器返回的错误应答原样传递给客户端
+        if let Some(reply) = error.downcast_ref::<Reply>() {
+            return *reply;
+        }
+        if let Some(error) = error.downcast_ref::<ConnectError>() {
+            return match error.status {
+                403 => Reply::NotAllowed,
+                502..=504 => Reply::HostUnreachable,
+                _ => Reply::GeneralFailure,
+            };
+        }
+
+        match error.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+            Some(ErrorKind::ConnectionRefused) => Reply::ConnectionRefused,
+            Some(ErrorKind::NetworkUnreachable) => Reply::NetworkUnreachable,
+            Some(ErrorKind::HostUnreachable) | Some(ErrorKind::NotFound) => Reply::HostUnreachable,
+            Some(ErrorKind::TimedOut) => Reply::TtlExpired,
+            _ => Reply::GeneralFailure,
+        }
+    }
+
+    // 编码应答: VER REP RSV ATYP BND.ADDR BND.PORT
+    pub fn encode(&self, bind: &Address) -> Result<BytesMut> {
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(VERSION);
+        buffer.put_u8(*self as u8);
+        buffer.put_u8(0x00);
+        write_address(&mut buffer, bind)?;
+        Ok(buffer)
+    }
+}
+
+impl Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Reply::Succeeded => "succeeded",
+            Reply::GeneralFailure => "general SOCKS server failure",
+            Reply::NotAllowed => "connection not allowed by ruleset",
+            Reply::NetworkUnreachable => "network unreachable",
+            Reply::HostUnreachable => "host unreachable",
+            Reply::ConnectionRefused => "connection refused",
+            Reply::TtlExpired => "TTL expired",
+            Reply::CommandNotSupported => "command not supported",
+            Reply::AddressTypeNotSupported => "address type not supported",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl std::error::Error for Reply {}
+
+// 按地址类型读取 DST.ADDR 和 DST.PORT
+pub async fn read_address<R: AsyncRead + Unpin>(reader: &mut R, atyp: u8) -> Result<Address> {
+    let address = match atyp {
+        ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            reader.read_exact(&mut ip).await?;
+            let port = reader.read_u16().await?;
+            Address::Ip(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::from(ip), port)))
+        }
+        ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            reader.read_exact(&mut ip).await?;
+            let port = reader.read_u16().await?;
+            Address::Ip(SocketAddr::V6(SocketAddrV6::new(
+                Ipv6Addr::from(ip),
+                port,
+                0,
+                0,
+            )))
+        }
+        ATYP_DOMAIN => {
+            let len = reader.read_u8().await?;
+            let mut domain = vec![0u8; len as usize];
+            reader.read_exact(&mut domain).await?;
+            let port = reader.read_u16().await?;
+            Address::Domain(String::from_utf8(domain)?, port)
+        }
+        _ => bail!("不支持的地址类型: {}", atyp),
+    };
+    Ok(address)
+}
+
+// 写入 ATYP、地址和端口
+pub fn write_address(buffer: &mut BytesMut, address: &Address) -> Result<()> {
+    match address {
+        Address::Ip(SocketAddr::V4(addr)) => {
+            buffer.put_u8(ATYP_IPV4);
+            buffer.put_slice(&addr.ip().octets());
+            buffer.put_u16(addr.port());
+        }
+        Address::Ip(SocketAddr::V6(addr)) => {
+            buffer.put_u8(ATYP_IPV6);
+            buffer.put_slice(&addr.ip().octets());
+            buffer.put_u16(addr.port());
+        }
+        Address::Domain(domain, port) => {
+            // 域名长度由一个字节表示
+            ensure!(domain.len() <= 255, "域名过长: {} 字节", domain.len());
+            buffer.put_u8(ATYP_DOMAIN);
+            buffer.put_u8(domain.len() as u8);
+            buffer.put_slice(domain.as_bytes());
+            buffer.put_u16(*port);
+        }
+    }
+    Ok(())
+}
+
+// 从字节缓冲区中解析 ATYP、地址和端口
+pub fn decode_address(buffer: &mut Bytes) -> Result<Address> {
+    ensure!(buffer.has_remaining(), "地址数据不完整");
+    let atyp = buffer.get_u8();
+    let address = match atyp {
+        ATYP_IPV4 => {
+            ensure!(buffer.remaining() >= 4 + 2, "IPv4 地址数据不完整");
+            let ip = Ipv4Addr::from(buffer.get_u32());
+            Address::Ip(SocketAddr::V4(SocketAddrV4::new(ip, buffer.get_u16())))
+        }
+        ATYP_IPV6 => {
+            ensure!(buffer.remaining() >= 16 + 2, "IPv6 地址数据不完整");
+            let ip = Ipv6Addr::from(buffer.get_u128());
+            Address::Ip(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                buffer.get_u16(),
+                0,
+                0,
+            )))
+        }
+        ATYP_DOMAIN => {
+            ensure!(buffer.has_remaining(), "域名数据不完整");
+            let len = buffer.get_u8() as usize;
+            ensure!(buffer.remaining() >= len + 2, "域名数据不完整");
+            let domain = String::from_utf8(buffer.split_to(len).to_vec())?;
+            Address::Domain(domain, buffer.get_u16())
+        }
+        _ => bail!("不支持的地址类型: {}", atyp),
+    };
+    Ok(address)
+}
+
+// UDP 数据报: RSV(2) FRAG(1) ATYP DST.ADDR DST.PORT DATA (RFC 1928 Section 7)
+pub fn encode_udp_packet(target: &Address, payload: &[u8]) -> Result<BytesMut> {
+    let mut buffer = BytesMut::with_capacity(payload.len() + 32);
+    buffer.put_u16(0x0000);
+    buffer.put_u8(0x00);
+    write_address(&mut buffer, target)?;
+    buffer.put_slice(payload);
+    Ok(buffer)
+}
+
+// 解析 UDP 数据报，返回目标地址和负载。不支持分片，分片的数据报会被拒绝
+pub fn decode_udp_packet(mut packet: Bytes) -> Result<(Address, Bytes)> {
+    ensure!(packet.remaining() >= 3, "UDP 数据报过短");
+    packet.advance(2);
+    let frag = packet.get_u8();
+    ensure!(frag == 0, "不支持分片的 UDP 数据报: {}", frag);
+    let target = decode_address(&mut packet)?;
+    Ok((target, packet))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn domain_length_limit() {
+        let mut buffer = BytesMut::new();
+        let address = Address::Domain("a".repeat(255), 443);
+        write_address(&mut buffer, &address).unwrap();
+        assert_eq!(decode_address(&mut buffer.freeze()).unwrap(), address);
+
+        let mut buffer = BytesMut::new();
+        let address = Address::Domain("a".repeat(256), 443);
+        assert!(write_address(&mut buffer, &address).is_err());
+        assert!(encode_udp_packet(&address, b"payload").is_err());
+    }
+}
@@ -1,12 +1,185 @@
-use anyhow::Result;
-use tokio::net::TcpStream;
+use std::net::SocketAddr;
 
-use super::Socks5;
+use anyhow::{Result, anyhow, bail, ensure};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+use tracing::debug;
+
+use super::{
+    Socks5,
+    model::{
+        AUTH_SUCCESS, AUTH_VERSION, COMMAND_CONNECT, COMMAND_UDP_ASSOCIATE, METHOD_NO_AUTH,
+        METHOD_USERNAME_PASSWORD, Reply, VERSION, decode_udp_packet, encode_udp_packet,
+        read_address, write_address,
+    },
+};
+use crate::protocol::{address::Address, udp::UDP_BUFFER_SIZE};
 
 impl Socks5 {
-    pub async fn connect(&self) -> Result<TcpStream> {
+    // 通过上游 SOCKS5 服务器连接目标地址，返回的连接可以直接转发数据
+    pub async fn connect(&self, target: &Address) -> Result<TcpStream> {
         let addr = format!("{}:{}", self.host, self.port);
-        let stream = TcpStream::connect(addr).await?;
+        let mut stream = TcpStream::connect(addr).await?;
+        self.handshake(&mut stream).await?;
+        let bind = self.request(&mut stream, COMMAND_CONNECT, target).await?;
+        debug!(
+            "出站 {} 连接 {} 成功, 绑定地址: {}",
+            self.name, target, bind
+        );
         Ok(stream)
     }
+
+    // 与上游建立 UDP 关联，返回可以收发 SOCKS5 UDP 数据报的套接字
+    pub async fn udp_associate(&self) -> Result<Socks5Datagram> {
+        let addr = format!("{}:{}", self.host, self.port);
+        let mut control = TcpStream::connect(addr).await?;
+        self.handshake(&mut control).await?;
+
+        // 客户端发送地址未知，按 RFC 1928 填写全零地址
+        let unspecified = Address::Ip(SocketAddr::from(([0, 0, 0, 0], 0)));
+        let relay = self
+            .request(&mut control, COMMAND_UDP_ASSOCIATE, &unspecified)
+            .await?;
+
+        // 上游返回的绑定地址为全零时，中继地址与上游服务器相同
+        let relay = match relay {
+            Address::Ip(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::new(control.peer_addr()?.ip(), addr.port())
+            }
+            Address::Ip(addr) => addr,
+            Address::Domain(domain, port) => tokio::net::lookup_host((domain.as_str(), port))
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("无法解析 UDP 中继地址: {}", domain))?,
+        };
+
+        let local: SocketAddr = if relay.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(relay).await?;
+
+        Ok(Socks5Datagram {
+            _control: control,
+            socket,
+        })
+    }
+
+    // 方法协商，必要时进行用户名/密码认证
+    pub async fn handshake<S>(&self, stream: &mut S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let method = if self.auth_enable {
+            METHOD_USERNAME_PASSWORD
+        } else {
+            METHOD_NO_AUTH
+        };
+        stream.write_all(&[VERSION, 1, method]).await?;
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await?;
+        ensure!(
+            response[0] == VERSION,
+            "不支持的 SOCKS 版本: {}",
+            response[0]
+        );
+        ensure!(
+            response[1] == method,
+            "出站 {} 不接受认证方法: {}",
+            self.name,
+            method
+        );
+
+        if self.auth_enable {
+            self.authenticate(stream).await?;
+        }
+        Ok(())
+    }
+
+    // 发送请求并读取应答，返回上游的绑定地址
+    pub async fn request<S>(&self, stream: &mut S, command: u8, target: &Address) -> Result<Address>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(VERSION);
+        buffer.put_u8(command);
+        buffer.put_u8(0x00);
+        write_address(&mut buffer, target)?;
+        stream.write_all(&buffer).await?;
+
+        // 应答: VER REP RSV ATYP BND.ADDR BND.PORT
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await?;
+        let [version, reply, _, atyp] = header;
+        ensure!(version == VERSION, "不支持的 SOCKS 版本: {}", version);
+        if reply != Reply::Succeeded as u8 {
+            match Reply::from_u8(reply) {
+                Some(reply) => {
+                    return Err(anyhow::Error::new(reply)
+                        .context(format!("出站 {} 返回错误应答", self.name)));
+                }
+                None => bail!("出站 {} 返回未知应答: {}", self.name, reply),
+            }
+        }
+        read_address(stream, atyp).await
+    }
+
+    // 向上游发送用户名/密码子协商: VER ULEN UNAME PLEN PASSWD
+    pub async fn authenticate<S>(&self, stream: &mut S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let username = self.username.as_deref().unwrap_or_default();
+        let password = self.password.as_deref().unwrap_or_default();
+        ensure!(
+            username.len() <= 255 && password.len() <= 255,
+            "出站 {} 的用户名或密码过长",
+            self.name
+        );
+
+        let mut buffer = BytesMut::new();
+        buffer.put_u8(AUTH_VERSION);
+        buffer.put_u8(username.len() as u8);
+        buffer.put_slice(username.as_bytes());
+        buffer.put_u8(password.len() as u8);
+        buffer.put_slice(password.as_bytes());
+        stream.write_all(&buffer).await?;
+
+        let mut response = [0u8; 2];
+        stream.read_exact(&mut response).await?;
+        if response[1] != AUTH_SUCCESS {
+            bail!("出站 {} 认证失败", self.name);
+        }
+        Ok(())
+    }
+}
+
+// 经由上游 SOCKS5 服务器收发 UDP 数据报。
+// 控制连接必须保持打开，关闭后上游会结束 UDP 关联
+pub struct Socks5Datagram {
+    _control: TcpStream,
+    socket: UdpSocket,
+}
+
+impl Socks5Datagram {
+    pub async fn send_to(&self, payload: &[u8], target: &Address) -> Result<()> {
+        self.socket
+            .send(&encode_udp_packet(target, payload)?)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn recv_from(&self) -> Result<(Bytes, Address)> {
+        let mut buffer = BytesMut::with_capacity(UDP_BUFFER_SIZE);
+        self.socket.recv_buf(&mut buffer).await?;
+        let (source, payload) = decode_udp_packet(buffer.freeze())?;
+        Ok((payload, source))
+    }
 }
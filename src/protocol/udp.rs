@@ -0,0 +1,30 @@
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{address::Address, direct::outbound::DirectDatagram, socks5::outbound::Socks5Datagram};
+
+// 单个 UDP 数据报的最大长度
+pub const UDP_BUFFER_SIZE: usize = 64 * 1024;
+
+// 出站的 UDP 通道
+pub enum Datagram {
+    Direct(DirectDatagram),
+    Socks5(Socks5Datagram),
+}
+
+impl Datagram {
+    pub async fn send_to(&self, payload: &[u8], target: &Address) -> Result<()> {
+        match self {
+            Datagram::Direct(datagram) => datagram.send_to(payload, target).await,
+            Datagram::Socks5(datagram) => datagram.send_to(payload, target).await,
+        }
+    }
+
+    // 返回 (负载, 来源地址)
+    pub async fn recv_from(&self) -> Result<(Bytes, Address)> {
+        match self {
+            Datagram::Direct(datagram) => datagram.recv_from().await,
+            Datagram::Socks5(datagram) => datagram.recv_from().await,
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, net::SocketAddr};
+
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+use super::{ServiceConfig, route::RouteContext};
+use crate::{
+    config::{control::Control, mode::Mode},
+    protocol::{
+        address::Address,
+        http::{
+            model::{RequestHead, ResponseHead},
+            read_head,
+        },
+    },
+};
+
+// 请求体的最大长度
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+// 控制接口 (HTTP):
+//   GET /mode          查询当前代理模式
+//   PUT /mode <mode>   切换代理模式 (rule / direct / global)，已建立的连接保持原有出站
+//   GET /rule          列出规则及其命中次数 (TCP 连接数与 UDP 流数)
+//   GET /explain?target=<host:port>[&inbound=<name>]
+//                      查询目标会经由哪个出站以及命中的规则
+impl Control {
+    pub async fn listen(&self) -> Result<()> {
+        let addr = format!("{}:{}", self.host, self.port);
+        let listener = TcpListener::bind(&addr).await?;
+        info!("控制接口启动在: {}", &addr);
+        loop {
+            match listener.accept().await {
+                Ok((stream, addr)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = accept(stream, addr).await {
+                            warn!("处理控制请求失败: {}", e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("来自 {} 的连接失败: {}", addr, e);
+                }
+            }
+        }
+    }
+}
+
+async fn accept(mut stream: TcpStream, addr: SocketAddr) -> Result<()> {
+    let mut buffer = BytesMut::new();
+    let head = read_head(&mut stream, &mut buffer).await?;
+    let request_head = RequestHead::decode(&head)?;
+    debug!(
+        "来自 {} 的控制请求: {} {}",
+        addr, request_head.method, request_head.uri
+    );
+
+    let length = request_head
+        .headers
+        .get("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .unwrap_or(0)
+        .min(MAX_BODY_SIZE);
+    while buffer.len() < length {
+        if stream.read_buf(&mut buffer).await? == 0 {
+            break;
+        }
+    }
+    let body = String::from_utf8_lossy(&buffer[..length.min(buffer.len())]).to_string();
+
+    let (status, body) = handle(&request_head, &body).await?;
+    stream.write_all(&response(status, &body)?).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+async fn handle(request_head: &RequestHead, body: &str) -> Result<(u16, String)> {
+    let service = ServiceConfig::get()?;
+    let route_manager = service.route_manager();
+    let (path, query) = request_head
+        .uri
+        .split_once('?')
+        .unwrap_or((&request_head.uri, ""));
+    let result = match (request_head.method.as_str(), path) {
+        ("GET", "/mode") => (200, service.mode().as_str().to_string()),
+        ("PUT", "/mode") | ("POST", "/mode") => match Mode::parse(body) {
+            Some(mode) => {
+                service.set_mode(mode);
+                info!("代理模式切换为: {:?}", mode);
+                (200, mode.as_str().to_string())
+            }
+            None => (400, format!("未知的代理模式: {}", body.trim())),
+        },
+        ("GET", "/rule") => {
+            let mut line_vec: Vec<String> = route_manager
+                .rule
+                .iter()
+                .enumerate()
+                .map(|(index, rule)| {
+                    let hit = route_manager.hit(Some(index));
+                    format!("{}\t{}\t{} -> {}", index, hit, rule, rule.outbound)
+                })
+                .collect();
+            let hit = route_manager.hit(None);
+            line_vec.push(format!("-\t{}\t默认出站 -> {}", hit, route_manager.default));
+            (200, line_vec.join("\n"))
+        }
+        ("GET", "/explain") => {
+            let param = parse_query(query);
+            match param
+                .get("target")
+                .map(|target| Address::parse(target, None))
+            {
+                Some(Ok(target)) => {
+                    let mut context = RouteContext::new(target);
+                    if let Some(inbound) = param.get("inbound") {
+                        context = context.with_inbound(inbound);
+                    }
+                    (200, service.explain(context).await)
+                }
+                Some(Err(e)) => (400, e.to_string()),
+                None => (400, "缺少参数 target".to_string()),
+            }
+        }
+        _ => (404, "未知的控制接口".to_string()),
+    };
+    Ok(result)
+}
+
+// 解析 "a=1&b=2" 形式的查询参数，不处理百分号编码
+fn parse_query(query: &str) -> HashMap<&str, &str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect()
+}
+
+fn response(status: u16, body: &str) -> Result<Bytes> {
+    let body = format!("{}\n", body);
+    let mut headers = HashMap::new();
+    headers.insert(
+        "content-type".to_string(),
+        "text/plain; charset=utf-8".to_string(),
+    );
+    headers.insert("content-length".to_string(), body.len().to_string());
+    headers.insert("connection".to_string(), "close".to_string());
+    let head = ResponseHead::encode(&ResponseHead {
+        status,
+        version: "HTTP/1.1".to_string(),
+        headers,
+    })?;
+    Ok(Bytes::from([head.as_ref(), body.as_bytes()].concat()))
+}
@@ -0,0 +1,412 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+
+use super::matcher::parse_regex;
+
+// geosite 分类中的一条域名规则
+#[derive(Debug, Clone)]
+pub enum GeoSiteDomain {
+    // 域名包含关键字
+    Keyword(String),
+    // 正则表达式
+    Regex(String),
+    // 域名后缀
+    Suffix(String),
+    // 域名完全匹配
+    Full(String),
+}
+
+// geosite 分类中的一条域名规则及其属性
+#[derive(Debug, Clone)]
+pub struct GeoSiteEntry {
+    pub domain: GeoSiteDomain,
+    // 属性名 (如 "cn")，均为小写
+    pub attribute_vec: Vec<String>,
+}
+
+impl GeoSiteEntry {
+    // 是否满足分类名中的属性要求: "属性" 必须带有，"!属性" 必须没有
+    fn has_attribute(&self, attribute_vec: &[String]) -> bool {
+        attribute_vec
+            .iter()
+            .all(|attribute| match attribute.strip_prefix('!') {
+                Some(attribute) => !self.attribute_vec.iter().any(|a| a == attribute),
+                None => self.attribute_vec.contains(attribute),
+            })
+    }
+}
+
+// 按分类名索引的 geosite 数据
+pub type GeoSite = HashMap<String, Vec<GeoSiteEntry>>;
+
+// 加载 geosite 数据中指定的分类，分类名不区分大小写。
+// path 为目录时按文本列表加载 (<dir>/<分类名> 或 <dir>/<分类名>.txt)，否则按 v2ray 的 geosite.dat 加载。
+// 数据中不存在的分类不会出现在返回结果中
+pub fn load_geosite(path: &str, code_vec: &[String]) -> Result<GeoSite> {
+    let code_set: HashSet<String> = code_vec.iter().map(|code| normalize_code(code)).collect();
+    if Path::new(path).is_dir() {
+        load_directory(path, &code_set)
+    } else {
+        let data =
+            std::fs::read(path).with_context(|| format!("读取 geosite 数据失败: {}", path))?;
+        decode_geosite_list(&data, &code_set)
+            .with_context(|| format!("解析 geosite 数据失败: {}", path))
+    }
+}
+
+// 去掉属性后的分类名，用于加载和索引 geosite 数据
+pub fn normalize_code(code: &str) -> String {
+    parse_code(code).0
+}
+
+// 规则中的分类名可以带有属性 (如 "google@cn"、"google@!cn")，返回 (分类名, 属性列表)，均为小写
+pub fn parse_code(code: &str) -> (String, Vec<String>) {
+    let mut part = code.split('@').map(|part| part.trim().to_ascii_lowercase());
+    let code = part.next().unwrap_or_default();
+    (
+        code,
+        part.filter(|attribute| !attribute.is_empty()).collect(),
+    )
+}
+
+// 取出分类名 (可以带有属性) 对应的域名规则，分类不存在时返回 None
+pub fn select_geosite<'a>(geosite: &'a GeoSite, code: &str) -> Option<Vec<&'a GeoSiteDomain>> {
+    let (code, attribute_vec) = parse_code(code);
+    let entry_vec = geosite.get(&code)?;
+    Some(
+        entry_vec
+            .iter()
+            .filter(|entry| entry.has_attribute(&attribute_vec))
+            .map(|entry| &entry.domain)
+            .collect(),
+    )
+}
+
+// ============================================================================
+// 文本列表 (v2fly domain-list-community 格式)
+// 每行一条: "domain:xx" / "full:xx" / "keyword:xx" / "regexp:xx" / "include:分类[@属性]"，
+// 不带前缀的视为 domain，之后可以跟 "@属性"，"#" 之后为注释
+// ============================================================================
+
+fn load_directory(dir: &str, code_set: &HashSet<String>) -> Result<GeoSite> {
+    let mut geosite = HashMap::new();
+    for code in code_set {
+        let mut domain_vec = Vec::new();
+        let mut visited = HashSet::new();
+        if load_list(dir, code, &mut domain_vec, &mut visited)? {
+            geosite.insert(code.clone(), domain_vec);
+        }
+    }
+    Ok(geosite)
+}
+
+// 读取一个分类及其 include 的分类，分类文件不存在时返回 false
+fn load_list(
+    dir: &str,
+    code: &str,
+    domain_vec: &mut Vec<GeoSiteEntry>,
+    visited: &mut HashSet<String>,
+) -> Result<bool> {
+    if !visited.insert(code.to_string()) {
+        return Ok(true);
+    }
+
+    let dir = Path::new(dir);
+    let path = [dir.join(code), dir.join(format!("{}.txt", code))]
+        .into_iter()
+        .find(|path| path.is_file());
+    let Some(path) = path else {
+        return Ok(false);
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("读取 geosite 列表失败: {}", path.display()))?;
+
+    for (number, line) in content.lines().enumerate() {
+        let line = line.split_once('#').map_or(line, |(line, _)| line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut token = line.split_whitespace();
+        let line = token.next().unwrap_or_default();
+        let attribute_vec = token
+            .filter_map(|token| token.strip_prefix('@'))
+            .map(str::to_ascii_lowercase)
+            .collect();
+        let (kind, value) = line.split_once(':').unwrap_or(("domain", line));
+        let domain = match kind {
+            "domain" => GeoSiteDomain::Suffix(value.to_string()),
+            "full" => GeoSiteDomain::Full(value.to_string()),
+            "keyword" => GeoSiteDomain::Keyword(value.to_string()),
+            "regexp" => {
+                parse_regex(value)
+                    .with_context(|| format!("{}:{} 正则无效", path.display(), number + 1))?;
+                GeoSiteDomain::Regex(value.to_string())
+            }
+            "include" => {
+                // 带有属性时只引用满足属性要求的域名
+                let (include, include_attribute_vec) = parse_code(value);
+                let mut include_vec = Vec::new();
+                let mut include_visited = visited.clone();
+                let (target_vec, target_visited) = match include_attribute_vec.is_empty() {
+                    true => (&mut *domain_vec, &mut *visited),
+                    false => (&mut include_vec, &mut include_visited),
+                };
+                if !load_list(
+                    dir.to_str().unwrap_or_default(),
+                    &include,
+                    target_vec,
+                    target_visited,
+                )? {
+                    bail!(
+                        "{}:{} 引用的分类不存在: {}",
+                        path.display(),
+                        number + 1,
+                        value
+                    );
+                }
+                domain_vec.extend(
+                    include_vec
+                        .into_iter()
+                        .filter(|entry| entry.has_attribute(&include_attribute_vec)),
+                );
+                continue;
+            }
+            _ => bail!("{}:{} 未知的规则类型: {}", path.display(), number + 1, kind),
+        };
+        domain_vec.push(GeoSiteEntry {
+            domain,
+            attribute_vec,
+        });
+    }
+    Ok(true)
+}
+
+// ============================================================================
+// v2ray geosite.dat (protobuf)
+//   GeoSiteList { repeated GeoSite entry = 1; }
+//   GeoSite     { string country_code = 1; repeated Domain domain = 2; }
+//   Domain      { Type type = 1; string value = 2; repeated Attribute attribute = 3; }
+//   Type        { Plain = 0; Regex = 1; Domain = 2; Full = 3; }
+//   Attribute   { string key = 1; oneof typed_value { bool bool_value = 2; int64 int_value = 3; } }
+// ============================================================================
+
+fn decode_geosite_list(data: &[u8], code_set: &HashSet<String>) -> Result<GeoSite> {
+    let mut geosite = HashMap::new();
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if let (1, ProtoValue::Bytes(entry)) = (field, value) {
+            decode_geosite(entry, code_set, &mut geosite)?;
+        }
+    }
+    Ok(geosite)
+}
+
+fn decode_geosite(data: &[u8], code_set: &HashSet<String>, geosite: &mut GeoSite) -> Result<()> {
+    let mut code = None;
+    let mut domain_data_vec = Vec::new();
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        match (field, value) {
+            (1, ProtoValue::Bytes(value)) => code = Some(normalize_code(str::from_utf8(value)?)),
+            (2, ProtoValue::Bytes(value)) => domain_data_vec.push(value),
+            _ => {}
+        }
+    }
+
+    // 只解码需要的分类
+    let Some(code) = code.filter(|code| code_set.contains(code)) else {
+        return Ok(());
+    };
+    let domain_vec = geosite.entry(code).or_default();
+    for domain_data in domain_data_vec {
+        domain_vec.push(decode_domain(domain_data)?);
+    }
+    Ok(())
+}
+
+fn decode_domain(data: &[u8]) -> Result<GeoSiteEntry> {
+    let mut kind = 0;
+    let mut value = String::new();
+    let mut attribute_vec = Vec::new();
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, field_value)) = reader.next_field()? {
+        match (field, field_value) {
+            (1, ProtoValue::Varint(v)) => kind = v,
+            (2, ProtoValue::Bytes(v)) => value = str::from_utf8(v)?.to_string(),
+            (3, ProtoValue::Bytes(v)) => attribute_vec.push(decode_attribute(v)?),
+            _ => {}
+        }
+    }
+    let domain = match kind {
+        0 => GeoSiteDomain::Keyword(value),
+        1 => {
+            parse_regex(&value)?;
+            GeoSiteDomain::Regex(value)
+        }
+        2 => GeoSiteDomain::Suffix(value),
+        3 => GeoSiteDomain::Full(value),
+        _ => bail!("未知的域名类型: {}", kind),
+    };
+    Ok(GeoSiteEntry {
+        domain,
+        attribute_vec,
+    })
+}
+
+// 只取属性名，属性值不参与匹配
+fn decode_attribute(data: &[u8]) -> Result<String> {
+    let mut key = String::new();
+    let mut reader = ProtoReader::new(data);
+    while let Some((field, value)) = reader.next_field()? {
+        if let (1, ProtoValue::Bytes(value)) = (field, value) {
+            key = str::from_utf8(value)?.to_ascii_lowercase();
+        }
+    }
+    Ok(key)
+}
+
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+// 最小的 protobuf 解码器，只支持按字段遍历
+struct ProtoReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ProtoReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'a>)>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x07 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => {
+                self.skip(8)?;
+                ProtoValue::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                ensure!(len <= self.data.len(), "protobuf 数据不完整");
+                let (value, rest) = self.data.split_at(len);
+                self.data = rest;
+                ProtoValue::Bytes(value)
+            }
+            5 => {
+                self.skip(4)?;
+                ProtoValue::Fixed
+            }
+            wire_type => bail!("不支持的 protobuf 类型: {}", wire_type),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .data
+                .split_first()
+                .ok_or_else(|| anyhow::anyhow!("protobuf 数据不完整"))?;
+            self.data = rest;
+            value |= u64::from(byte & 0x7F) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("无效的 protobuf varint")
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        ensure!(len <= self.data.len(), "protobuf 数据不完整");
+        self.data = &self.data[len..];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn domain_string(geosite: &GeoSite, code: &str) -> Vec<String> {
+        select_geosite(geosite, code)
+            .unwrap()
+            .into_iter()
+            .map(|domain| format!("{:?}", domain))
+            .collect()
+    }
+
+    #[test]
+    fn text_attribute() {
+        let dir = std::env::temp_dir().join(format!("x-proxy-geosite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("google"),
+            "google.com\nfull:www.google.cn @cn # 注释\ninclude:ads@cn\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("ads.txt"),
+            "ads.google.com @ads\nads.google.cn @ads @cn\n",
+        )
+        .unwrap();
+
+        let code_vec = ["google@cn".to_string(), "Google@!cn".to_string()];
+        let geosite = load_geosite(dir.to_str().unwrap(), &code_vec).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            domain_string(&geosite, "google@cn"),
+            ["Full(\"www.google.cn\")", "Suffix(\"ads.google.cn\")"]
+        );
+        assert_eq!(
+            domain_string(&geosite, "google@!cn"),
+            ["Suffix(\"google.com\")"]
+        );
+        assert_eq!(domain_string(&geosite, "google").len(), 3);
+        assert!(domain_string(&geosite, "google@ads@!cn").is_empty());
+        assert!(select_geosite(&geosite, "youtube").is_none());
+    }
+
+    fn field(number: u8, value: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![number << 3 | 2, value.len() as u8];
+        buffer.extend_from_slice(value);
+        buffer
+    }
+
+    #[test]
+    fn dat_attribute() {
+        // Domain { type = Full, value = "www.google.cn", attribute = [{ key = "cn", bool_value = true }] }
+        let mut cn = vec![0x08, 0x03];
+        cn.extend(field(2, b"www.google.cn"));
+        let mut attribute = field(1, b"cn");
+        attribute.extend([0x10, 0x01]);
+        cn.extend(field(3, &attribute));
+        let mut com = vec![0x08, 0x02];
+        com.extend(field(2, b"google.com"));
+
+        let mut entry = field(1, b"GOOGLE");
+        entry.extend(field(2, &cn));
+        entry.extend(field(2, &com));
+        let data = field(1, &entry);
+
+        let code_set = HashSet::from(["google".to_string()]);
+        let geosite = decode_geosite_list(&data, &code_set).unwrap();
+        assert_eq!(
+            domain_string(&geosite, "google@CN"),
+            ["Full(\"www.google.cn\")"]
+        );
+        assert_eq!(domain_string(&geosite, "google").len(), 2);
+    }
+}
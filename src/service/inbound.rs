@@ -6,6 +6,12 @@ pub struct InboundManager {
     pub inbound_hash_map: HashMap<String, Inbound>,
 }
 
+impl Default for InboundManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl InboundManager {
     pub fn new() -> Self {
         Self {
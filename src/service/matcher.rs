@@ -0,0 +1,309 @@
+use std::{collections::HashMap, net::IpAddr, ops::RangeInclusive, sync::Arc};
+
+use anyhow::{Context, Result, anyhow, ensure};
+use maxminddb::{Mmap, Reader, geoip2};
+use regex::{Regex, RegexSet};
+
+// 按反转标签组织的域名前缀树: "www.google.com" 依次插入 com -> google -> www。
+// 每个节点记录完整匹配和后缀匹配对应的最小规则序号，查找时只需遍历目标域名的标签
+#[derive(Debug, Default)]
+pub struct DomainMatcher {
+    root: DomainNode,
+}
+
+#[derive(Debug, Default)]
+struct DomainNode {
+    children: HashMap<Box<str>, DomainNode>,
+    // 域名完全等于该节点时命中的规则
+    full: Option<usize>,
+    // 域名等于该节点或为其子域名时命中的规则
+    suffix: Option<usize>,
+}
+
+impl DomainMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_full(&mut self, domain: &str, index: usize) {
+        let node = self.node_mut(domain);
+        node.full = Some(node.full.map_or(index, |old| old.min(index)));
+    }
+
+    pub fn insert_suffix(&mut self, domain: &str, index: usize) {
+        // ".google.com" 与 "google.com" 作为后缀是等价的
+        let node = self.node_mut(domain.trim_start_matches('.'));
+        node.suffix = Some(node.suffix.map_or(index, |old| old.min(index)));
+    }
+
+    // 返回命中的最小规则序号
+    pub fn find(&self, domain: &str) -> Option<usize> {
+        let domain = normalize(domain);
+        let mut best: Option<usize> = None;
+        let mut node = &self.root;
+        for label in domain.rsplit('.') {
+            node = match node.children.get(label) {
+                Some(child) => child,
+                None => return best,
+            };
+            best = min_index(best, node.suffix);
+        }
+        min_index(best, node.full)
+    }
+
+    fn node_mut(&mut self, domain: &str) -> &mut DomainNode {
+        let domain = normalize(domain);
+        let mut node = &mut self.root;
+        for label in domain.rsplit('.') {
+            node = node.children.entry(label.into()).or_default();
+        }
+        node
+    }
+}
+
+// 域名不区分大小写，末尾的根标签 "." 不参与匹配
+fn normalize(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+// 域名正则，同一出站的正则合并为一个 RegexSet 一次匹配，记录每个正则对应的规则序号
+#[derive(Debug, Default)]
+pub struct RegexMatcher {
+    set_vec: Vec<(RegexSet, Vec<usize>)>,
+}
+
+impl RegexMatcher {
+    // 参数为每个出站的 (正则, 规则序号) 列表，任一正则无效或编译失败时返回错误
+    pub fn new(pattern_hash_map: HashMap<String, Vec<(String, usize)>>) -> Result<Self> {
+        let mut set_vec = Vec::new();
+        for (outbound, pattern_vec) in pattern_hash_map {
+            for (pattern, index) in &pattern_vec {
+                parse_regex(pattern).with_context(|| format!("规则 {} 无效", index))?;
+            }
+            let set = RegexSet::new(pattern_vec.iter().map(|(pattern, _)| pattern))
+                .with_context(|| format!("出站 {} 的域名正则编译失败", outbound))?;
+            set_vec.push((
+                set,
+                pattern_vec.into_iter().map(|(_, index)| index).collect(),
+            ));
+        }
+        Ok(Self { set_vec })
+    }
+
+    // 返回命中的最小规则序号
+    pub fn find(&self, domain: &str) -> Option<usize> {
+        let domain = normalize(domain);
+        self.set_vec
+            .iter()
+            .flat_map(|(set, index_vec)| {
+                set.matches(&domain)
+                    .into_iter()
+                    .map(|pattern| index_vec[pattern])
+            })
+            .min()
+    }
+}
+
+// 检查域名正则是否有效
+pub fn parse_regex(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| anyhow!("无效的域名正则 '{}': {}", pattern, e))
+}
+
+pub fn min_index(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// IP 地址前缀树，IPv4 和 IPv6 分别按位组织，记录命中的最小规则序号
+#[derive(Debug, Default)]
+pub struct IpMatcher {
+    v4: BitNode,
+    v6: BitNode,
+}
+
+#[derive(Debug, Default)]
+struct BitNode {
+    children: [Option<Box<BitNode>>; 2],
+    index: Option<usize>,
+}
+
+impl IpMatcher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, ip: IpAddr, prefix: u8, index: usize) {
+        match ip {
+            IpAddr::V4(ip) => {
+                insert_bits(&mut self.v4, u128::from(ip.to_bits()) << 96, prefix, index)
+            }
+            IpAddr::V6(ip) => {
+                let bits = ip.to_bits();
+                insert_bits(&mut self.v6, bits, prefix, index);
+                // find 把 IPv4 映射的 IPv6 地址按 IPv4 匹配，与 ::ffff:0:0/96 重叠的网段也要插入 IPv4 树
+                let mapped_prefix = u32::from(prefix.min(96));
+                if mapped_prefix == 0 || (bits ^ IPV4_MAPPED) >> (128 - mapped_prefix) == 0 {
+                    insert_bits(&mut self.v4, bits << 96, prefix.saturating_sub(96), index);
+                }
+            }
+        }
+    }
+
+    // 返回命中的最小规则序号，IPv4 映射的 IPv6 地址按 IPv4 匹配
+    pub fn find(&self, ip: IpAddr) -> Option<usize> {
+        let (root, bits, len) = match ip.to_canonical() {
+            IpAddr::V4(ip) => (&self.v4, u128::from(ip.to_bits()) << 96, 32),
+            IpAddr::V6(ip) => (&self.v6, ip.to_bits(), 128),
+        };
+        let mut best = root.index;
+        let mut node = root;
+        for i in 0..len {
+            let bit = ((bits >> (127 - i)) & 1) as usize;
+            node = match &node.children[bit] {
+                Some(child) => child,
+                None => break,
+            };
+            best = min_index(best, node.index);
+        }
+        best
+    }
+}
+
+// IPv4 映射的 IPv6 地址前缀 ::ffff:0:0/96
+const IPV4_MAPPED: u128 = 0xffff << 32;
+
+fn insert_bits(root: &mut BitNode, bits: u128, prefix: u8, index: usize) {
+    let mut node = root;
+    for i in 0..prefix {
+        let bit = ((bits >> (127 - i)) & 1) as usize;
+        node = node.children[bit].get_or_insert_with(Default::default);
+    }
+    node.index = Some(node.index.map_or(index, |old| old.min(index)));
+}
+
+// 解析 "10.0.0.0/8"、"fc00::/7" 形式的网段，单个地址视为完整前缀
+pub fn parse_cidr(cidr: &str) -> Result<(IpAddr, u8)> {
+    let cidr = cidr.trim();
+    let (ip, prefix) = match cidr.split_once('/') {
+        Some((ip, prefix)) => (ip, Some(prefix)),
+        None => (cidr, None),
+    };
+    let ip: IpAddr = ip
+        .parse()
+        .map_err(|_| anyhow!("无效的 IP 网段: {}", cidr))?;
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .parse::<u8>()
+            .map_err(|_| anyhow!("无效的 IP 网段: {}", cidr))?,
+        None => max,
+    };
+    ensure!(prefix <= max, "无效的 IP 网段前缀长度: {}", cidr);
+    Ok((ip, prefix))
+}
+
+// 解析 "443" 或 "8000-9000" 形式的端口范围
+pub fn parse_port_range(range: &str) -> Result<RangeInclusive<u16>> {
+    let range = range.trim();
+    let parse = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .map_err(|_| anyhow!("无效的端口范围: {}", range))
+    };
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(range)?, parse(range)?),
+    };
+    ensure!(start <= end, "无效的端口范围: {}", range);
+    Ok(start..=end)
+}
+
+pub fn parse_uid(uid: &str) -> Result<u32> {
+    uid.trim()
+        .parse::<u32>()
+        .map_err(|_| anyhow!("无效的 UID: {}", uid))
+}
+
+// GeoIP 数据库 (MaxMind mmdb 格式)，以内存映射方式打开
+pub type GeoIpReader = Reader<Mmap>;
+
+pub fn open_geoip(path: &str) -> Result<GeoIpReader> {
+    Reader::open_mmap(path).with_context(|| format!("打开 GeoIP 数据库失败: {}", path))
+}
+
+// 私有和保留地址，对应 geoip 规则中的 "private"
+const PRIVATE_CIDR: [&str; 13] = [
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "fc00::/7",
+    "fe80::/10",
+];
+
+// 按国家代码匹配目标 IP，记录每个国家代码命中的最小规则序号
+pub struct GeoIpMatcher {
+    reader: Option<Arc<GeoIpReader>>,
+    country_hash_map: HashMap<String, usize>,
+    private: Option<usize>,
+    private_matcher: IpMatcher,
+}
+
+impl GeoIpMatcher {
+    pub fn new(reader: Option<Arc<GeoIpReader>>) -> Self {
+        let mut private_matcher = IpMatcher::new();
+        for cidr in PRIVATE_CIDR {
+            if let Ok((ip, prefix)) = parse_cidr(cidr) {
+                private_matcher.insert(ip, prefix, 0);
+            }
+        }
+        Self {
+            reader,
+            country_hash_map: HashMap::new(),
+            private: None,
+            private_matcher,
+        }
+    }
+
+    pub fn insert(&mut self, code: &str, index: usize) {
+        let code = code.trim().to_ascii_uppercase();
+        if code == "PRIVATE" {
+            self.private = min_index(self.private, Some(index));
+        } else {
+            let slot = self.country_hash_map.entry(code).or_insert(index);
+            *slot = (*slot).min(index);
+        }
+    }
+
+    pub fn find(&self, ip: IpAddr) -> Option<usize> {
+        let ip = ip.to_canonical();
+        let mut best = None;
+        if self.private_matcher.find(ip).is_some() {
+            best = self.private;
+        }
+        if self.country_hash_map.is_empty() {
+            return best;
+        }
+        if let Some(reader) = &self.reader
+            && let Ok(country) = reader.lookup::<geoip2::Country>(ip)
+            && let Some(code) = country.country.and_then(|country| country.iso_code)
+        {
+            let index = self
+                .country_hash_map
+                .get(&code.to_ascii_uppercase())
+                .copied();
+            best = min_index(best, index);
+        }
+        best
+    }
+}
@@ -1,22 +1,41 @@
+pub mod control;
+pub mod geosite;
 pub mod inbound;
+pub mod matcher;
 pub mod outbound;
+pub mod process;
 pub mod route;
+pub mod rule_set;
 
-use std::sync::OnceLock;
+use std::sync::{Arc, OnceLock, RwLock};
 
 use anyhow::Result;
 use inbound::InboundManager;
 use outbound::OutboundManager;
-use route::RouteManager;
+use process::Network;
+use route::{RouteContext, RouteManager};
+use tracing::{debug, info};
 
-use crate::config::Config;
+use crate::{
+    config::{
+        Config,
+        mode::Mode,
+        outbound::Outbound,
+        router::{Router, RuleSet},
+    },
+    protocol::stream::ProxyStream,
+};
 
 pub static SERVICE_CONFIG: OnceLock<ServiceConfig> = OnceLock::new();
 
 pub struct ServiceConfig {
     pub inbound_manager: InboundManager,
     pub outbound_manager: OutboundManager,
-    pub route_manager: RouteManager,
+    // 当前代理模式，可以在运行时切换，不随路由规则重新加载
+    mode: RwLock<Mode>,
+    // 远程规则集更新后整体替换
+    route_manager: RwLock<Arc<RouteManager>>,
+    router: Router,
 }
 
 impl ServiceConfig {
@@ -25,29 +44,130 @@ impl ServiceConfig {
         let inbound_manager = InboundManager::init(inbound);
         let outbound = config.outbound.clone();
         let outbound_manager = OutboundManager::init(outbound);
-        let rule_list = config.router.init();
-        let global = config.router.global.clone();
-        let default = config.router.default.clone();
-        let route_manager = RouteManager::init(rule_list, global, default);
+        let route_manager = Self::load_route(&config.router)?;
         if let Err(_e) = SERVICE_CONFIG.set(ServiceConfig {
             inbound_manager,
             outbound_manager,
-            route_manager,
+            mode: RwLock::new(config.mode),
+            route_manager: RwLock::new(Arc::new(route_manager)),
+            router: config.router.clone(),
         }) {
             return Err(anyhow::anyhow!("SERVICE_CONFIG 已被初始化"));
         };
         Ok(())
     }
 
+    fn load_route(router: &Router) -> Result<RouteManager> {
+        let (rule_list, geosite) = router.init()?;
+        let global = router.global.clone();
+        let default = router.default.clone();
+        let geoip = router.load_geoip()?;
+        RouteManager::init(rule_list, default, global, geoip, geosite)
+    }
+
+    pub fn mode(&self) -> Mode {
+        *self.mode.read().unwrap()
+    }
+
+    // 切换代理模式，只影响之后建立的连接
+    pub fn set_mode(&self, mode: Mode) {
+        *self.mode.write().unwrap() = mode;
+    }
+
+    pub fn route_manager(&self) -> Arc<RouteManager> {
+        self.route_manager.read().unwrap().clone()
+    }
+
+    // 重新加载路由规则 (包括规则集)，之后建立的连接使用新规则
+    pub fn reload_route(&self) -> Result<()> {
+        let route_manager = Self::load_route(&self.router)?;
+        *self.route_manager.write().unwrap() = Arc::new(route_manager);
+        info!("路由规则已重新加载");
+        Ok(())
+    }
+
+    pub fn get() -> Result<&'static ServiceConfig> {
+        SERVICE_CONFIG
+            .get()
+            .ok_or_else(|| anyhow::anyhow!("SERVICE_CONFIG 尚未初始化"))
+    }
+
+    // 根据代理模式和路由规则为连接选择出站，规则模式下计入规则命中次数
+    pub async fn route(&self, mut context: RouteContext) -> Result<Outbound> {
+        let route_manager = self.route_manager();
+        let outbound_name = match self.mode() {
+            // 直连模式绕过所有上游，不受同名自定义出站影响
+            Mode::Direct => {
+                return Ok(Outbound::direct());
+            }
+            Mode::Global => &route_manager.global,
+            Mode::Rule => {
+                Self::prepare(&route_manager, &mut context).await;
+                let route_match = route_manager.switch(&context);
+                debug!("{} 命中{}", context.target, route_match);
+                route_match.outbound
+            }
+        };
+        self.outbound_manager
+            .get(outbound_name)
+            .ok_or_else(|| anyhow::anyhow!("出站 '{}' 没有找到", outbound_name))
+    }
+
+    // 查询连接会经由哪个出站以及原因，不建立连接也不计入规则命中次数
+    pub async fn explain(&self, mut context: RouteContext) -> String {
+        let route_manager = self.route_manager();
+        let explain = match self.mode() {
+            Mode::Direct => "直连模式 -> direct".to_string(),
+            Mode::Global => format!("全局模式 -> {}", route_manager.global),
+            Mode::Rule => {
+                Self::prepare(&route_manager, &mut context).await;
+                format!("规则模式, {}", route_manager.explain(&context))
+            }
+        };
+        format!("{}: {}", context.target, explain)
+    }
+
+    // 查找规则需要的进程信息和域名解析结果
+    async fn prepare(route_manager: &RouteManager, context: &mut RouteContext) {
+        if route_manager.need_process(context) {
+            context
+                .lookup_process(Network::Tcp, route_manager.need_pid())
+                .await;
+        }
+        if route_manager.need_resolve(context) {
+            context.resolve().await;
+        }
+    }
+
+    // 根据路由规则为连接选择出站，并建立到出站的连接
+    pub async fn connect(&self, context: RouteContext) -> Result<ProxyStream> {
+        let target = context.target.clone();
+        let destination = context.destination();
+        let outbound = self.route(context).await?;
+        info!("{} 经由出站 {}", target, outbound.name());
+        outbound.connect(&destination).await
+    }
+
     pub async fn init(config: &Config) -> Result<()> {
-        // 启动日志
-        config.info.init();
+        info!("代理模式: {:?}", config.mode);
 
         // 启动入站监听
         for inbound in &config.inbound {
             inbound.init().await;
         }
 
+        // 启动远程规则集更新
+        for rule_set in &config.router.rule_set {
+            if let RuleSet::RuleSetRemote(remote) = rule_set {
+                tokio::spawn(rule_set::refresh(remote.clone()));
+            }
+        }
+
+        // 启动控制接口
+        if let Some(control) = &config.control {
+            control.init().await;
+        }
+
         Ok(())
     }
 }
@@ -6,6 +6,12 @@ pub struct OutboundManager {
     pub outbound_hash_map: HashMap<String, Outbound>,
 }
 
+impl Default for OutboundManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl OutboundManager {
     pub fn new() -> Self {
         Self {
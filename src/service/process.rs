@@ -0,0 +1,239 @@
+use std::net::SocketAddr;
+
+// 本机客户端连接所属的进程信息
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub uid: u32,
+    pub pid: Option<u32>,
+    // 进程名 (/proc/<pid>/comm)
+    pub name: Option<String>,
+    // 可执行文件路径 (/proc/<pid>/exe)
+    pub path: Option<String>,
+}
+
+// 客户端连接使用的传输协议，决定查找哪些套接字表
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Tcp,
+    Udp,
+}
+
+// 查找以 source 为本端地址的套接字所属的 UID，find_pid 时再查找持有该套接字的进程。
+// 只有客户端在本机时才能找到
+#[cfg(target_os = "linux")]
+pub async fn lookup_process(
+    source: SocketAddr,
+    network: Network,
+    find_pid: bool,
+) -> Option<ProcessInfo> {
+    tokio::task::spawn_blocking(move || linux::lookup(source, network, find_pid))
+        .await
+        .ok()
+        .flatten()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub async fn lookup_process(
+    _source: SocketAddr,
+    _network: Network,
+    _find_pid: bool,
+) -> Option<ProcessInfo> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    };
+
+    use tracing::trace;
+
+    use super::{Network, ProcessInfo};
+
+    const TCP_TABLE: [&str; 2] = ["/proc/net/tcp", "/proc/net/tcp6"];
+    const UDP_TABLE: [&str; 2] = ["/proc/net/udp", "/proc/net/udp6"];
+
+    pub fn lookup(source: SocketAddr, network: Network, find_pid: bool) -> Option<ProcessInfo> {
+        let source = SocketAddr::new(source.ip().to_canonical(), source.port());
+        // 其他主机的客户端在本机没有对应的套接字，按端口匹配只会找到无关的进程
+        if !is_local(source.ip()) {
+            return None;
+        }
+        let table = match network {
+            Network::Tcp => TCP_TABLE,
+            Network::Udp => UDP_TABLE,
+        };
+        let (uid, inode) = table
+            .iter()
+            .find_map(|path| find_socket(path, source, false))
+            // 没有 connect 的 UDP 套接字本端地址为通配地址
+            .or_else(|| match network {
+                Network::Tcp => None,
+                Network::Udp => table
+                    .iter()
+                    .find_map(|path| find_socket(path, source, true)),
+            })?;
+        let mut process = ProcessInfo {
+            uid,
+            pid: None,
+            name: None,
+            path: None,
+        };
+        if find_pid && let Some(pid) = find_pid_by_inode(inode) {
+            process.pid = Some(pid);
+            process.name = fs::read_to_string(format!("/proc/{}/comm", pid))
+                .ok()
+                .map(|name| name.trim_end().to_string());
+            process.path = fs::read_link(format!("/proc/{}/exe", pid))
+                .ok()
+                .map(|path| path.to_string_lossy().into_owned());
+        }
+        trace!("{} 所属进程: {:?}", source, process);
+        Some(process)
+    }
+
+    // 地址是否属于本机: 回环地址，或者可以绑定的地址
+    fn is_local(ip: IpAddr) -> bool {
+        ip.is_loopback() || UdpSocket::bind(SocketAddr::new(ip, 0)).is_ok()
+    }
+
+    // 每行: sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode。
+    // wildcard 时匹配绑定在通配地址上的同端口套接字
+    fn find_socket(path: &str, source: SocketAddr, wildcard: bool) -> Option<(u32, u64)> {
+        let content = fs::read_to_string(path).ok()?;
+        content.lines().skip(1).find_map(|line| {
+            let field: Vec<&str> = line.split_whitespace().collect();
+            if field.len() < 10 {
+                return None;
+            }
+            let local = parse_socket_addr(field[1])?;
+            let matched = match wildcard {
+                true => local.ip().is_unspecified() && local.port() == source.port(),
+                false => local == source,
+            };
+            if !matched {
+                return None;
+            }
+            Some((field[7].parse().ok()?, field[9].parse().ok()?))
+        })
+    }
+
+    // 遍历所有进程的文件描述符，找到指向 socket:[inode] 的进程
+    fn find_pid_by_inode(inode: u64) -> Option<u32> {
+        let target = format!("socket:[{}]", inode);
+        fs::read_dir("/proc").ok()?.flatten().find_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+            let fd_dir = fs::read_dir(entry.path().join("fd")).ok()?;
+            fd_dir
+                .flatten()
+                .any(|fd| fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == &*target))
+                .then_some(pid)
+        })
+    }
+
+    // 解析 /proc/net 中 "0100007F:1F90" 形式的地址。
+    // IP 按内核中的网络字节序逐个 32 位字以本机字节序输出，端口为主机字节序
+    fn parse_socket_addr(addr: &str) -> Option<SocketAddr> {
+        let (ip, port) = addr.split_once(':')?;
+        let port = u16::from_str_radix(port, 16).ok()?;
+        let mut bytes = Vec::with_capacity(16);
+        for i in (0..ip.len()).step_by(8) {
+            let word = u32::from_str_radix(ip.get(i..i + 8)?, 16).ok()?;
+            bytes.extend_from_slice(&word.to_ne_bytes());
+        }
+        let ip = match bytes.len() {
+            4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+            16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+            _ => return None,
+        };
+        // 双栈监听时客户端地址可能是 IPv4 映射的 IPv6 地址
+        Some(SocketAddr::new(ip.to_canonical(), port))
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::net::{TcpListener, TcpStream};
+
+        use super::*;
+
+        fn current_uid() -> u32 {
+            let status = fs::read_to_string("/proc/self/status").unwrap();
+            let line = status
+                .lines()
+                .find(|line| line.starts_with("Uid:"))
+                .unwrap();
+            line.split_whitespace().nth(1).unwrap().parse().unwrap()
+        }
+
+        // /proc/net 中的地址按本机字节序输出，以下样例取自小端主机
+        #[cfg(target_endian = "little")]
+        #[test]
+        fn parse_proc_net_addr() {
+            // /proc/net/tcp: 127.0.0.1:8080 LISTEN
+            let line = "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 23456 1 0000000000000000 100 0 0 10 0";
+            let field: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(
+                parse_socket_addr(field[1]),
+                Some("127.0.0.1:8080".parse().unwrap())
+            );
+            // /proc/net/tcp: 192.168.1.10:22 <- 192.168.1.20:51234 ESTABLISHED
+            let line = "   1: 0A01A8C0:0016 1401A8C0:C822 01 00000000:00000000 02:0009C3A6 00000000     0        0 34567 4 0000000000000000 20 4 31 10 -1";
+            let field: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(
+                parse_socket_addr(field[2]),
+                Some("192.168.1.20:51234".parse().unwrap())
+            );
+            // /proc/net/tcp6: [::1]:631 LISTEN
+            let line = "   0: 00000000000000000000000001000000:0277 00000000000000000000000000000000:0000 0A 00000000:00000000 00:00000000 00000000     0        0 45678 1 0000000000000000 100 0 0 10 0";
+            let field: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(
+                parse_socket_addr(field[1]),
+                Some("[::1]:631".parse().unwrap())
+            );
+            // /proc/net/tcp6: 双栈套接字上 IPv4 映射的 IPv6 地址 ::ffff:127.0.0.1:8080
+            let line = "   1: 0000000000000000FFFF00000100007F:1F90 0000000000000000FFFF00000100007F:D431 01 00000000:00000000 00:00000000 00000000  1000        0 56789 1 0000000000000000 20 4 30 10 -1";
+            let field: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(
+                parse_socket_addr(field[1]),
+                Some("127.0.0.1:8080".parse().unwrap())
+            );
+            assert_eq!(parse_socket_addr("0100007F"), None);
+            assert_eq!(parse_socket_addr("0100007:1F90"), None);
+        }
+
+        #[test]
+        fn lookup_tcp_client() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let source = client.local_addr().unwrap();
+
+            let process = lookup(source, Network::Tcp, true).unwrap();
+            assert_eq!(process.uid, current_uid());
+            assert_eq!(process.pid, Some(std::process::id()));
+            assert_eq!(
+                process.name,
+                fs::read_to_string("/proc/self/comm")
+                    .ok()
+                    .map(|name| name.trim_end().to_string())
+            );
+            // 同一地址不会出现在 UDP 表中
+            assert!(lookup(source, Network::Udp, false).is_none());
+        }
+
+        #[test]
+        fn lookup_wildcard_udp() {
+            let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+            let port = socket.local_addr().unwrap().port();
+
+            let source = SocketAddr::from(([127, 0, 0, 1], port));
+            let process = lookup(source, Network::Udp, false).unwrap();
+            assert_eq!(process.uid, current_uid());
+            // 通配地址只用于 UDP，也只用于本机客户端
+            assert!(lookup(source, Network::Tcp, false).is_none());
+            let remote = SocketAddr::from(([192, 0, 2, 1], port));
+            assert!(lookup(remote, Network::Udp, false).is_none());
+        }
+    }
+}
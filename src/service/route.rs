@@ -1,22 +1,323 @@
-use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+};
 
-use crate::config::router::{Rule, RuleSet, RuleType};
+use anyhow::Result;
+use tokio::net::lookup_host;
+use tracing::{debug, warn};
 
-#[derive(Debug, Deserialize)]
-pub struct Route {
-    pub rule: Vec<Rule>,
-    pub rule_set: Vec<RuleSet>,
-    pub default: String,
+use super::{
+    geosite::{GeoSite, GeoSiteDomain, select_geosite},
+    matcher::{
+        DomainMatcher, GeoIpMatcher, GeoIpReader, IpMatcher, RegexMatcher, min_index, parse_cidr,
+        parse_port_range, parse_uid,
+    },
+    process::{Network, ProcessInfo, lookup_process},
+};
+use crate::{
+    config::router::{Rule, RuleType},
+    protocol::address::Address,
+};
+
+// 路由匹配所需的连接信息
+#[derive(Debug, Clone)]
+pub struct RouteContext {
+    pub target: Address,
+    // 客户端地址
+    pub source: Option<SocketAddr>,
+    // 接收连接的入站名
+    pub inbound: Option<String>,
+    // 本机客户端所属的进程，只在有规则需要时才查找
+    pub process: Option<ProcessInfo>,
+    // 是否已经查找过进程，没有找到时 process 为 None
+    pub process_looked_up: bool,
+    // 域名目标解析得到的 IP 地址，只在有规则需要时才解析
+    pub resolved_ip: Vec<IpAddr>,
+    // 嗅探到域名前客户端请求的 IP 目标，IP 规则仍按它匹配
+    pub sniffed_ip: Option<SocketAddr>,
+    // 是否连接嗅探到的域名，否则仍连接客户端请求的 IP
+    pub sniff_override_destination: bool,
+}
+
+impl RouteContext {
+    pub fn new(target: Address) -> Self {
+        Self {
+            target,
+            source: None,
+            inbound: None,
+            process: None,
+            process_looked_up: false,
+            resolved_ip: Vec::new(),
+            sniffed_ip: None,
+            sniff_override_destination: false,
+        }
+    }
+
+    pub fn with_source(mut self, source: SocketAddr) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_inbound(mut self, inbound: &str) -> Self {
+        self.inbound = Some(inbound.to_string());
+        self
+    }
+
+    // 使用嗅探到的域名作为路由目标，只替换 IP 目标
+    pub fn with_sniffed(mut self, domain: Option<String>, override_destination: bool) -> Self {
+        if let (Some(domain), Address::Ip(addr)) = (domain, &self.target) {
+            self.sniffed_ip = Some(*addr);
+            self.sniff_override_destination = override_destination;
+            self.target = Address::Domain(domain, addr.port());
+        }
+        self
+    }
+
+    // 实际连接的目标地址
+    pub fn destination(&self) -> Address {
+        match self.sniffed_ip {
+            Some(addr) if !self.sniff_override_destination => Address::Ip(addr),
+            _ => self.target.clone(),
+        }
+    }
+
+    pub async fn resolve(&mut self) {
+        if let Address::Domain(domain, port) = &self.target {
+            match lookup_host((domain.as_str(), *port)).await {
+                Ok(addrs) => self.resolved_ip = addrs.map(|addr| addr.ip()).collect(),
+                Err(e) => debug!("路由时解析 {} 失败: {}", domain, e),
+            }
+        }
+    }
+
+    // 使用之前查找到的进程，同一客户端地址的多次路由不必重复查找
+    pub fn with_process(mut self, process: Option<ProcessInfo>) -> Self {
+        self.process = process;
+        self.process_looked_up = true;
+        self
+    }
+
+    pub async fn lookup_process(&mut self, network: Network, find_pid: bool) {
+        if let Some(source) = self.source {
+            self.process = lookup_process(source, network, find_pid).await;
+        }
+        self.process_looked_up = true;
+    }
+}
+
+// 无法建立索引、需要逐条匹配的规则，以及 and/or/not 规则的子规则
+enum LinearMatcher {
+    // 域名包含任一关键字
+    Keyword(Vec<String>),
+    // 目标端口属于任一范围
+    DstPort(Vec<RangeInclusive<u16>>),
+    // 客户端端口属于任一范围
+    SrcPort(Vec<RangeInclusive<u16>>),
+    // 域名完全或后缀匹配
+    Domain(DomainMatcher),
+    // 域名匹配任一正则
+    Regex(RegexMatcher),
+    // 目标 IP 属于网段，resolve 时也匹配域名目标的解析结果
+    Ip(IpMatcher, bool),
+    GeoIp(GeoIpMatcher, bool),
+    // 客户端 IP 属于网段
+    SrcIp(IpMatcher),
+    // 连接来自任一入站
+    Inbound(Vec<String>),
+    // 本机客户端的进程名、可执行文件路径或用户 ID 属于任一值
+    ProcessName(Vec<String>),
+    ProcessPath(Vec<String>),
+    Uid(Vec<u32>),
+    And(Vec<LinearMatcher>),
+    Or(Vec<LinearMatcher>),
+    Not(Box<LinearMatcher>),
+}
+
+impl LinearMatcher {
+    // 为 and/or/not 规则中的单条规则构建匹配器
+    fn build(
+        index: usize,
+        rule: &Rule,
+        geoip: &Option<Arc<GeoIpReader>>,
+        geosite: &GeoSite,
+    ) -> Result<Self> {
+        let build_vec = |rule_vec: &[Rule]| {
+            rule_vec
+                .iter()
+                .map(|rule| Self::build(index, rule, geoip, geosite))
+                .collect::<Result<Vec<_>>>()
+        };
+        let matcher = match rule.r#type {
+            RuleType::Domain | RuleType::DomainFull => {
+                let mut matcher = DomainMatcher::new();
+                for domain in &rule.source {
+                    matcher.insert_full(domain, index);
+                }
+                LinearMatcher::Domain(matcher)
+            }
+            RuleType::DomainSuffix => {
+                let mut matcher = DomainMatcher::new();
+                for domain in &rule.source {
+                    matcher.insert_suffix(domain, index);
+                }
+                LinearMatcher::Domain(matcher)
+            }
+            RuleType::Keyword => LinearMatcher::Keyword(rule.source.clone()),
+            RuleType::DomainRegex => {
+                let pattern_vec = rule.source.iter().map(|pattern| (pattern.clone(), index));
+                LinearMatcher::Regex(single_regex_matcher(pattern_vec.collect())?)
+            }
+            RuleType::IpCidr | RuleType::IpCidr6 => {
+                let mut matcher = IpMatcher::new();
+                for (ip, prefix) in parse_source(index, &rule.source, parse_cidr) {
+                    matcher.insert(ip, prefix, index);
+                }
+                LinearMatcher::Ip(matcher, rule.resolve)
+            }
+            RuleType::GeoIp => {
+                let mut matcher = GeoIpMatcher::new(geoip.clone());
+                for code in &rule.source {
+                    matcher.insert(code, index);
+                }
+                LinearMatcher::GeoIp(matcher, rule.resolve)
+            }
+            RuleType::GeoSite => {
+                let mut matcher = DomainMatcher::new();
+                let mut pattern_vec = Vec::new();
+                let keyword_vec =
+                    insert_geosite(index, rule, geosite, &mut matcher, &mut pattern_vec);
+                LinearMatcher::Or(vec![
+                    LinearMatcher::Domain(matcher),
+                    LinearMatcher::Keyword(keyword_vec),
+                    LinearMatcher::Regex(single_regex_matcher(pattern_vec)?),
+                ])
+            }
+            RuleType::DstPort => {
+                LinearMatcher::DstPort(parse_source(index, &rule.source, parse_port_range))
+            }
+            RuleType::SrcIpCidr => {
+                let mut matcher = IpMatcher::new();
+                for (ip, prefix) in parse_source(index, &rule.source, parse_cidr) {
+                    matcher.insert(ip, prefix, index);
+                }
+                LinearMatcher::SrcIp(matcher)
+            }
+            RuleType::SrcPort => {
+                LinearMatcher::SrcPort(parse_source(index, &rule.source, parse_port_range))
+            }
+            RuleType::Inbound => LinearMatcher::Inbound(rule.source.clone()),
+            RuleType::ProcessName => LinearMatcher::ProcessName(rule.source.clone()),
+            RuleType::ProcessPath => LinearMatcher::ProcessPath(rule.source.clone()),
+            RuleType::Uid => LinearMatcher::Uid(parse_source(index, &rule.source, parse_uid)),
+            RuleType::And => LinearMatcher::And(build_vec(&rule.rule)?),
+            RuleType::Or => LinearMatcher::Or(build_vec(&rule.rule)?),
+            RuleType::Not => match rule.rule.first() {
+                Some(rule) => {
+                    LinearMatcher::Not(Box::new(Self::build(index, rule, geoip, geosite)?))
+                }
+                // 没有子规则的 not 规则不会命中
+                None => LinearMatcher::Or(Vec::new()),
+            },
+        };
+        Ok(matcher)
+    }
+
+    fn is_match(&self, context: &RouteContext) -> bool {
+        match self {
+            LinearMatcher::Keyword(keyword_vec) => {
+                let host = context.target.host();
+                keyword_vec
+                    .iter()
+                    .any(|keyword| host.contains(keyword.as_str()))
+            }
+            LinearMatcher::DstPort(range_vec) => {
+                let port = context.target.port();
+                range_vec.iter().any(|range| range.contains(&port))
+            }
+            LinearMatcher::SrcPort(range_vec) => context
+                .source
+                .is_some_and(|source| range_vec.iter().any(|range| range.contains(&source.port()))),
+            LinearMatcher::Domain(matcher) => match &context.target {
+                Address::Domain(domain, _) => matcher.find(domain).is_some(),
+                Address::Ip(_) => false,
+            },
+            LinearMatcher::Regex(matcher) => match &context.target {
+                Address::Domain(domain, _) => matcher.find(domain).is_some(),
+                Address::Ip(_) => false,
+            },
+            LinearMatcher::Ip(matcher, resolve) => target_ip(context, *resolve)
+                .into_iter()
+                .any(|ip| matcher.find(ip).is_some()),
+            LinearMatcher::GeoIp(matcher, resolve) => target_ip(context, *resolve)
+                .into_iter()
+                .any(|ip| matcher.find(ip).is_some()),
+            LinearMatcher::SrcIp(matcher) => context
+                .source
+                .is_some_and(|source| matcher.find(source.ip()).is_some()),
+            LinearMatcher::Inbound(name_vec) => context
+                .inbound
+                .as_ref()
+                .is_some_and(|inbound| name_vec.contains(inbound)),
+            LinearMatcher::ProcessName(name_vec) => context
+                .process
+                .as_ref()
+                .and_then(|process| process.name.as_ref())
+                .is_some_and(|name| name_vec.contains(name)),
+            LinearMatcher::ProcessPath(path_vec) => context
+                .process
+                .as_ref()
+                .and_then(|process| process.path.as_ref())
+                .is_some_and(|path| path_vec.contains(path)),
+            LinearMatcher::Uid(uid_vec) => context
+                .process
+                .as_ref()
+                .is_some_and(|process| uid_vec.contains(&process.uid)),
+            LinearMatcher::And(matcher_vec) => {
+                matcher_vec.iter().all(|matcher| matcher.is_match(context))
+            }
+            LinearMatcher::Or(matcher_vec) => {
+                matcher_vec.iter().any(|matcher| matcher.is_match(context))
+            }
+            LinearMatcher::Not(matcher) => !matcher.is_match(context),
+        }
+    }
 }
 
-impl Route {
-    pub fn init(&self) -> Vec<Rule> {
-        let mut rule_list = Vec::new();
-        for rule_set in &self.rule_set {
-            rule_list.extend(rule_set.init());
+// IP 规则用于匹配的目标地址: IP 目标本身，嗅探前的 IP 目标，或允许解析时域名目标的解析结果
+fn target_ip(context: &RouteContext, resolve: bool) -> Vec<IpAddr> {
+    if let Some(addr) = context.sniffed_ip {
+        return vec![addr.ip()];
+    }
+    match &context.target {
+        Address::Ip(addr) => vec![addr.ip()],
+        Address::Domain(..) if resolve => context.resolved_ip.clone(),
+        Address::Domain(..) => Vec::new(),
+    }
+}
+
+// 路由结果: 命中的规则及其出站，没有规则命中时使用默认出站
+#[derive(Debug, Clone, Copy)]
+pub struct RouteMatch<'a> {
+    pub index: Option<usize>,
+    pub rule: Option<&'a Rule>,
+    pub outbound: &'a str,
+}
+
+impl Display for RouteMatch<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.index, self.rule) {
+            (Some(index), Some(rule)) => {
+                write!(f, "规则 {}: {} -> {}", index, rule, self.outbound)
+            }
+            _ => write!(f, "默认出站 -> {}", self.outbound),
         }
-        rule_list.extend(self.rule.clone());
-        rule_list
     }
 }
 
@@ -24,34 +325,351 @@ pub struct RouteManager {
     pub rule: Vec<Rule>,
     pub global: String,
     pub default: String,
+    // 所有域名类规则合并后的前缀树
+    domain_matcher: DomainMatcher,
+    // 所有域名正则，按出站合并
+    regex_matcher: RegexMatcher,
+    // 所有 IP 规则合并后的前缀树
+    ip_matcher: IpMatcher,
+    // 允许解析域名的 IP 规则，用于匹配域名目标的解析结果
+    resolved_ip_matcher: IpMatcher,
+    // 所有 GeoIP 规则，以及其中允许解析域名的部分
+    geoip_matcher: GeoIpMatcher,
+    resolved_geoip_matcher: GeoIpMatcher,
+    // 所有客户端 IP 规则合并后的前缀树
+    src_ip_matcher: IpMatcher,
+    // 入站名对应的第一条入站规则
+    inbound_hash_map: HashMap<String, usize>,
+    // 进程名、可执行文件路径和用户 ID 对应的第一条规则
+    process_name_hash_map: HashMap<String, usize>,
+    process_path_hash_map: HashMap<String, usize>,
+    uid_hash_map: HashMap<u32, usize>,
+    // 是否有规则需要查找客户端进程，以及是否需要进程名或路径
+    process_rule: bool,
+    pid_rule: bool,
+    // 第一条允许解析域名的 IP 规则
+    resolve_rule_first: Option<usize>,
+    // 需要逐条匹配的规则，按规则序号排列
+    linear_rule: Vec<(usize, LinearMatcher)>,
+    // 每条规则和默认出站的命中次数，重新加载规则后清零
+    hit_vec: Vec<AtomicU64>,
+    default_hit: AtomicU64,
 }
 
 impl RouteManager {
-    pub fn init(rule: Vec<Rule>, default: String, global: String) -> Self {
-        RouteManager {
-            rule,
-            global,
-            default,
-        }
-    }
-
-    pub fn switch(&self, target_host: &str) -> &str {
-        for rule in &self.rule {
+    pub fn init(
+        rule: Vec<Rule>,
+        default: String,
+        global: String,
+        geoip: Option<Arc<GeoIpReader>>,
+        geosite: GeoSite,
+    ) -> Result<Self> {
+        let mut domain_matcher = DomainMatcher::new();
+        let mut regex_hash_map: HashMap<String, Vec<(String, usize)>> = HashMap::new();
+        let mut ip_matcher = IpMatcher::new();
+        let mut resolved_ip_matcher = IpMatcher::new();
+        let mut geoip_matcher = GeoIpMatcher::new(geoip.clone());
+        let mut resolved_geoip_matcher = GeoIpMatcher::new(geoip.clone());
+        let mut src_ip_matcher = IpMatcher::new();
+        let mut inbound_hash_map = HashMap::new();
+        let mut process_name_hash_map = HashMap::new();
+        let mut process_path_hash_map = HashMap::new();
+        let mut uid_hash_map = HashMap::new();
+        let mut resolve_rule_first = None;
+        let mut linear_rule = Vec::new();
+        for (index, rule) in rule.iter().enumerate() {
             match rule.r#type {
-                RuleType::Domain => {
-                    if rule.source.contains(&target_host.to_string()) {
-                        return &rule.outbound;
+                RuleType::Domain | RuleType::DomainFull => {
+                    for domain in &rule.source {
+                        domain_matcher.insert_full(domain, index);
                     }
                 }
-                RuleType::Keyword => {
-                    for keyword in &rule.source {
-                        if target_host.contains(keyword) {
-                            return &rule.outbound;
+                RuleType::DomainSuffix => {
+                    for domain in &rule.source {
+                        domain_matcher.insert_suffix(domain, index);
+                    }
+                }
+                RuleType::IpCidr | RuleType::IpCidr6 => {
+                    for (ip, prefix) in parse_source(index, &rule.source, parse_cidr) {
+                        ip_matcher.insert(ip, prefix, index);
+                        if rule.resolve {
+                            resolved_ip_matcher.insert(ip, prefix, index);
                         }
                     }
+                    if rule.resolve && resolve_rule_first.is_none() {
+                        resolve_rule_first = Some(index);
+                    }
+                }
+                RuleType::GeoIp => {
+                    for code in &rule.source {
+                        geoip_matcher.insert(code, index);
+                        if rule.resolve {
+                            resolved_geoip_matcher.insert(code, index);
+                        }
+                    }
+                    if rule.resolve && resolve_rule_first.is_none() {
+                        resolve_rule_first = Some(index);
+                    }
+                }
+                RuleType::DomainRegex => {
+                    let pattern_vec = regex_hash_map.entry(rule.outbound.clone()).or_default();
+                    for pattern in &rule.source {
+                        pattern_vec.push((pattern.clone(), index));
+                    }
+                }
+                RuleType::GeoSite => {
+                    let pattern_vec = regex_hash_map.entry(rule.outbound.clone()).or_default();
+                    let keyword_vec =
+                        insert_geosite(index, rule, &geosite, &mut domain_matcher, pattern_vec);
+                    if !keyword_vec.is_empty() {
+                        linear_rule.push((index, LinearMatcher::Keyword(keyword_vec)));
+                    }
+                }
+                RuleType::Keyword => {
+                    linear_rule.push((index, LinearMatcher::Keyword(rule.source.clone())));
+                }
+                RuleType::DstPort => {
+                    let range_vec = parse_source(index, &rule.source, parse_port_range);
+                    linear_rule.push((index, LinearMatcher::DstPort(range_vec)));
+                }
+                RuleType::SrcPort => {
+                    let range_vec = parse_source(index, &rule.source, parse_port_range);
+                    linear_rule.push((index, LinearMatcher::SrcPort(range_vec)));
+                }
+                RuleType::SrcIpCidr => {
+                    for (ip, prefix) in parse_source(index, &rule.source, parse_cidr) {
+                        src_ip_matcher.insert(ip, prefix, index);
+                    }
+                }
+                RuleType::Inbound => {
+                    for name in &rule.source {
+                        inbound_hash_map.entry(name.clone()).or_insert(index);
+                    }
+                }
+                RuleType::ProcessName => {
+                    for name in &rule.source {
+                        process_name_hash_map.entry(name.clone()).or_insert(index);
+                    }
+                }
+                RuleType::ProcessPath => {
+                    for path in &rule.source {
+                        process_path_hash_map.entry(path.clone()).or_insert(index);
+                    }
+                }
+                RuleType::Uid => {
+                    for uid in parse_source(index, &rule.source, parse_uid) {
+                        uid_hash_map.entry(uid).or_insert(index);
+                    }
+                }
+                RuleType::And | RuleType::Or | RuleType::Not => {
+                    let matcher = LinearMatcher::build(index, rule, &geoip, &geosite)?;
+                    linear_rule.push((index, matcher));
+                    let resolve = rule.flatten().into_iter().any(|rule| {
+                        rule.resolve
+                            && matches!(
+                                rule.r#type,
+                                RuleType::IpCidr | RuleType::IpCidr6 | RuleType::GeoIp
+                            )
+                    });
+                    if resolve && resolve_rule_first.is_none() {
+                        resolve_rule_first = Some(index);
+                    }
                 }
             }
         }
-        &self.default
+
+        let leaf_type_vec: Vec<&RuleType> = rule
+            .iter()
+            .flat_map(Rule::flatten)
+            .map(|rule| &rule.r#type)
+            .collect();
+        let pid_rule = leaf_type_vec
+            .iter()
+            .any(|r#type| matches!(r#type, RuleType::ProcessName | RuleType::ProcessPath));
+        let process_rule = pid_rule
+            || leaf_type_vec
+                .iter()
+                .any(|r#type| matches!(r#type, RuleType::Uid));
+
+        let hit_vec = rule.iter().map(|_| AtomicU64::new(0)).collect();
+        Ok(RouteManager {
+            rule,
+            global,
+            default,
+            domain_matcher,
+            regex_matcher: RegexMatcher::new(regex_hash_map)?,
+            ip_matcher,
+            resolved_ip_matcher,
+            geoip_matcher,
+            resolved_geoip_matcher,
+            src_ip_matcher,
+            inbound_hash_map,
+            process_name_hash_map,
+            process_path_hash_map,
+            uid_hash_map,
+            process_rule,
+            pid_rule,
+            resolve_rule_first,
+            linear_rule,
+            hit_vec,
+            default_hit: AtomicU64::new(0),
+        })
+    }
+
+    // 域名目标是否需要先解析: 只有允许解析的 IP 规则排在域名规则命中之前时才需要
+    pub fn need_resolve(&self, context: &RouteContext) -> bool {
+        let Address::Domain(domain, _) = &context.target else {
+            return false;
+        };
+        // 嗅探得到的域名已经有客户端请求的 IP，不需要再解析
+        if context.sniffed_ip.is_some() {
+            return false;
+        }
+        self.resolve_rule_first
+            .is_some_and(|first| self.find_domain(domain).is_none_or(|best| first < best))
+    }
+
+    // 是否需要先查找本机客户端所属的进程
+    pub fn need_process(&self, context: &RouteContext) -> bool {
+        self.process_rule && context.source.is_some() && !context.process_looked_up
     }
+
+    // 查找进程时是否需要进程名和路径，只需要用户 ID 时可以跳过遍历进程
+    pub fn need_pid(&self) -> bool {
+        self.pid_rule
+    }
+
+    // 域名前缀树和域名正则中最先命中的规则
+    fn find_domain(&self, domain: &str) -> Option<usize> {
+        min_index(
+            self.domain_matcher.find(domain),
+            self.regex_matcher.find(domain),
+        )
+    }
+
+    // 选择出站并记录规则的命中次数。每个 TCP 连接或 UDP 流只调用一次，
+    // 命中次数按连接统计，UDP 流之后的数据报需要复用第一次的结果
+    pub fn switch(&self, context: &RouteContext) -> RouteMatch<'_> {
+        let route_match = self.explain(context);
+        match route_match.index {
+            Some(index) => self.hit_vec[index].fetch_add(1, Ordering::Relaxed),
+            None => self.default_hit.fetch_add(1, Ordering::Relaxed),
+        };
+        route_match
+    }
+
+    // 选择出站但不记录命中次数，用于查询路由结果
+    pub fn explain(&self, context: &RouteContext) -> RouteMatch<'_> {
+        let index = self.find(context);
+        RouteMatch {
+            index,
+            rule: index.map(|index| &self.rule[index]),
+            outbound: index.map_or(&self.default, |index| &self.rule[index].outbound),
+        }
+    }
+
+    // 规则的命中次数，index 为 None 时为默认出站的命中次数
+    pub fn hit(&self, index: Option<usize>) -> u64 {
+        match index {
+            Some(index) => self.hit_vec[index].load(Ordering::Relaxed),
+            None => self.default_hit.load(Ordering::Relaxed),
+        }
+    }
+
+    // 按规则顺序查找最先命中的规则，排在前面的规则优先。
+    // 可以建立索引的规则通过前缀树一次查出最先命中的规则，其余规则只需检查排在它之前的部分
+    fn find(&self, context: &RouteContext) -> Option<usize> {
+        let mut best = match &context.target {
+            Address::Ip(addr) => min_index(
+                self.ip_matcher.find(addr.ip()),
+                self.geoip_matcher.find(addr.ip()),
+            ),
+            Address::Domain(domain, _) => {
+                context
+                    .resolved_ip
+                    .iter()
+                    .fold(self.find_domain(domain), |best, ip| {
+                        let index = min_index(
+                            self.resolved_ip_matcher.find(*ip),
+                            self.resolved_geoip_matcher.find(*ip),
+                        );
+                        min_index(best, index)
+                    })
+            }
+        };
+        if let Some(addr) = context.sniffed_ip {
+            best = min_index(best, self.ip_matcher.find(addr.ip()));
+            best = min_index(best, self.geoip_matcher.find(addr.ip()));
+        }
+        if let Some(source) = context.source {
+            best = min_index(best, self.src_ip_matcher.find(source.ip()));
+        }
+        if let Some(inbound) = &context.inbound {
+            best = min_index(best, self.inbound_hash_map.get(inbound).copied());
+        }
+        if let Some(process) = &context.process {
+            best = min_index(best, self.uid_hash_map.get(&process.uid).copied());
+            if let Some(name) = &process.name {
+                best = min_index(best, self.process_name_hash_map.get(name).copied());
+            }
+            if let Some(path) = &process.path {
+                best = min_index(best, self.process_path_hash_map.get(path).copied());
+            }
+        }
+
+        for (index, matcher) in &self.linear_rule {
+            if best.is_some_and(|best| best < *index) {
+                break;
+            }
+            if matcher.is_match(context) {
+                return Some(*index);
+            }
+        }
+        best
+    }
+}
+
+// 解析规则的 source 列表，无效的条目记录警告后跳过
+fn parse_source<T>(index: usize, source: &[String], parse: impl Fn(&str) -> Result<T>) -> Vec<T> {
+    source
+        .iter()
+        .filter_map(|item| match parse(item) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warn!("忽略规则 {} 的条目: {}", index, e);
+                None
+            }
+        })
+        .collect()
+}
+
+// 将规则引用的 geosite 分类插入域名前缀树并收集其中的正则，返回需要逐条匹配的关键字
+fn insert_geosite(
+    index: usize,
+    rule: &Rule,
+    geosite: &GeoSite,
+    domain_matcher: &mut DomainMatcher,
+    pattern_vec: &mut Vec<(String, usize)>,
+) -> Vec<String> {
+    let mut keyword_vec = Vec::new();
+    for code in &rule.source {
+        let Some(domain_vec) = select_geosite(geosite, code) else {
+            warn!("规则 {} 的 geosite 分类 '{}' 没有找到", index, code);
+            continue;
+        };
+        for domain in domain_vec {
+            match domain {
+                GeoSiteDomain::Full(domain) => domain_matcher.insert_full(domain, index),
+                GeoSiteDomain::Suffix(domain) => domain_matcher.insert_suffix(domain, index),
+                GeoSiteDomain::Keyword(keyword) => keyword_vec.push(keyword.clone()),
+                GeoSiteDomain::Regex(pattern) => pattern_vec.push((pattern.clone(), index)),
+            }
+        }
+    }
+    keyword_vec
+}
+
+// 单条规则使用的正则匹配器
+fn single_regex_matcher(pattern_vec: Vec<(String, usize)>) -> Result<RegexMatcher> {
+    RegexMatcher::new(HashMap::from([(String::new(), pattern_vec)]))
 }
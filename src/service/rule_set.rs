@@ -0,0 +1,96 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, anyhow};
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+use super::ServiceConfig;
+use crate::{
+    config::{
+        outbound::Outbound,
+        router::{RuleSet, RuleSetRemote},
+    },
+    protocol::http::client,
+};
+
+// 首次下载失败且没有缓存时的重试间隔，每次失败翻倍，最长不超过更新间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+// 远程规则集的更新任务: 启动时使用缓存，缓存过期或不存在时下载。
+// 下载成功后写入缓存并重新加载路由规则，失败时继续使用缓存，等待下一次更新；
+// 还没有缓存时按退避间隔尽快重试，避免规则集在整个更新间隔内缺失
+pub async fn refresh(remote: RuleSetRemote) {
+    let interval = Duration::from_secs(remote.update_interval);
+    sleep(interval.saturating_sub(cache_age(&remote.path))).await;
+    let mut retry = RETRY_INTERVAL.min(interval);
+    loop {
+        match download(&remote).await {
+            Ok(()) => {
+                if let Err(e) = ServiceConfig::get().and_then(|service| service.reload_route()) {
+                    warn!("规则集 '{}' 更新后重新加载路由失败: {:#}", remote.name, e);
+                }
+            }
+            Err(e) if !Path::new(&remote.path).exists() => {
+                warn!(
+                    "规则集 '{}' 下载失败，{} 秒后重试: {:#}",
+                    remote.name,
+                    retry.as_secs(),
+                    e
+                );
+                sleep(retry).await;
+                retry = (retry * 2).min(interval);
+                continue;
+            }
+            Err(e) => warn!("规则集 '{}' 更新失败，继续使用缓存: {:#}", remote.name, e),
+        }
+        sleep(interval).await;
+    }
+}
+
+// 缓存文件距上次修改的时间，不存在时视为已过期
+fn cache_age(path: &str) -> Duration {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .unwrap_or(Duration::MAX)
+}
+
+// 下载规则集，内容可以解析时才写入缓存
+async fn download(remote: &RuleSetRemote) -> Result<()> {
+    let service = ServiceConfig::get()?;
+    let outbound = match &remote.download_outbound {
+        Some(name) => service
+            .outbound_manager
+            .get(name)
+            .ok_or_else(|| anyhow!("出站 '{}' 没有找到", name))?,
+        None => Outbound::direct(),
+    };
+
+    let body = client::get(&remote.url, &outbound).await?;
+    let rule_vec = RuleSet::RuleSetRemote(remote.clone())
+        .parse(&body)
+        .with_context(|| format!("下载的规则集无效: {}", remote.url))?;
+    service.router.check_geoip(&rule_vec)?;
+    let geosite = service.router.load_geosite(rule_vec.iter())?;
+    service.router.check_geosite(&rule_vec, &geosite)?;
+
+    // 先写入临时文件再替换，避免写入中断时损坏缓存
+    let temp_path = format!("{}.tmp", remote.path);
+    tokio::fs::write(&temp_path, &body)
+        .await
+        .with_context(|| format!("写入规则集缓存失败: {}", temp_path))?;
+    tokio::fs::rename(&temp_path, &remote.path)
+        .await
+        .with_context(|| format!("写入规则集缓存失败: {}", remote.path))?;
+    info!(
+        "规则集 '{}' 已从 {} 更新，共 {} 条规则",
+        remote.name,
+        remote.url,
+        rule_vec.len()
+    );
+    Ok(())
+}